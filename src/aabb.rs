@@ -0,0 +1,126 @@
+use crate::{vector3::Vector3, ray::Ray};
+
+// axis-aligned bounding box, stored as its two opposite corners
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct Aabb {
+    pub min: Vector3,
+    pub max: Vector3,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3, max: Vector3) -> Self {
+        Aabb {min, max}
+    }
+    pub fn empty() -> Self {
+        // inverted box, so that any union with it returns the other box
+        Aabb {
+            min: Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+    pub fn from_points(a: Vector3, b: Vector3) -> Self {
+        Aabb::empty().grow(a).grow(b)
+    }
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vector3::new(f64::min(self.min.x, other.min.x), f64::min(self.min.y, other.min.y), f64::min(self.min.z, other.min.z)),
+            max: Vector3::new(f64::max(self.max.x, other.max.x), f64::max(self.max.y, other.max.y), f64::max(self.max.z, other.max.z)),
+        }
+    }
+    pub fn grow(&self, p: Vector3) -> Aabb {
+        self.union(&Aabb::new(p, p))
+    }
+    // widens the axes thinner than `delta`, so flat shapes still get a box rays can hit
+    pub fn pad(&self, delta: f64) -> Aabb {
+        let d = self.extent();
+        let grow = |extent: f64| if extent < delta { delta / 2.0 } else { 0.0 };
+        let g = Vector3::new(grow(d.x), grow(d.y), grow(d.z));
+
+        Aabb::new(self.min - g, self.max + g)
+    }
+    pub fn centroid(&self) -> Vector3 {
+        (self.min + self.max) * 0.5
+    }
+    pub fn extent(&self) -> Vector3 {
+        self.max - self.min
+    }
+    pub fn surface_area(&self) -> f64 {
+        let d = self.extent();
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0
+        }
+        2.0 * (d.x*d.y + d.y*d.z + d.z*d.x)
+    }
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+    pub fn is_finite(&self) -> bool {
+        self.min.x.is_finite() && self.min.y.is_finite() && self.min.z.is_finite()
+            && self.max.x.is_finite() && self.max.y.is_finite() && self.max.z.is_finite()
+    }
+
+    // slab test, returns the distance at which the ray enters the box
+    pub fn hit(&self, ray: &Ray, inv_dir: Vector3, t_min: f64, t_max: f64) -> Option<f64> {
+        let mut t0 = t_min;
+        let mut t1 = t_max;
+        for axis in 0..3 {
+            let mut t_near = (self.min[axis] - ray.origin[axis]) * inv_dir[axis];
+            let mut t_far = (self.max[axis] - ray.origin[axis]) * inv_dir[axis];
+            if inv_dir[axis] < 0.0 {
+                std::mem::swap(&mut t_near, &mut t_far);
+            }
+            // max/min ignore the NaN produced by 0 * inf when the ray lies on a slab plane
+            t0 = f64::max(t_near, t0);
+            t1 = f64::min(t_far, t1);
+            if t1 < t0 {
+                return None
+            }
+        }
+        Some(t0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Aabb;
+    use crate::{vector3::Vector3, ray::Ray};
+
+    fn inv(v: Vector3) -> Vector3 {
+        Vector3::new(1.0/v.x, 1.0/v.y, 1.0/v.z)
+    }
+    #[test]
+    fn union_test() {
+        let a = Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+        let b = Aabb::new(Vector3::new(-1.0, 0.5, 0.0), Vector3::new(0.5, 2.0, 1.0));
+        let res = Aabb::new(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(1.0, 2.0, 1.0));
+
+        assert_eq!(res, a.union(&b));
+        assert_eq!(a, Aabb::empty().union(&a));
+    }
+    #[test]
+    fn surface_area_test() {
+        let a = Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 2.0, 3.0));
+
+        assert_eq!(2.0*(2.0 + 6.0 + 3.0), a.surface_area());
+        assert_eq!(0.0, Aabb::empty().surface_area());
+    }
+    #[test]
+    fn hit_test() {
+        let a = Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        let towards = Ray::new(Vector3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        let away = Ray::new(Vector3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, -1.0));
+        let beside = Ray::new(Vector3::new(2.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+
+        assert_eq!(Some(4.0), a.hit(&towards, inv(towards.direction), 0.0, f64::INFINITY));
+        assert_eq!(None, a.hit(&away, inv(away.direction), 0.0, f64::INFINITY));
+        assert_eq!(None, a.hit(&beside, inv(beside.direction), 0.0, f64::INFINITY));
+        assert_eq!(None, a.hit(&towards, inv(towards.direction), 0.0, 3.0));
+    }
+}
@@ -0,0 +1,206 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::{color::RGBColor, framebuffer::Framebuffer, utils::clamp, vector3::Vector3};
+
+// radiance coming from directions where rays leave the scene
+#[derive(Clone)]
+pub enum Background {
+    Solid(RGBColor),
+    // blend from `bottom` looking straight down to `top` looking straight up
+    Gradient {bottom: RGBColor, top: RGBColor},
+    Environment(Arc<EnvironmentMap>),
+    Sky(Sky),
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        // white to blue
+        Background::Gradient {bottom: RGBColor::new(1.0, 1.0, 1.0), top: RGBColor::new(0.5, 0.7, 1.0)}
+    }
+}
+
+impl Background {
+    pub fn color(&self, direction: Vector3) -> RGBColor {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient {bottom, top} => {
+                let unit_direction = direction.unit();
+                let t = 0.5*(unit_direction.y + 1.0);
+
+                *bottom*(1.0 - t) + *top * t
+            }
+            Background::Environment(map) => map.color(direction),
+            Background::Sky(sky) => sky.color(direction),
+        }
+    }
+}
+
+// equirectangular (latitude/longitude) image around the scene, with +y up. The center of
+// the image looks towards -z
+pub struct EnvironmentMap {
+    image: Framebuffer,
+    pub intensity: f64,
+    // turns the map around the y axis, in degrees
+    pub rotation: f64,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Framebuffer, intensity: f64, rotation: f64) -> Self {
+        assert!(image.width() > 0 && image.height() > 0, "Environment image is empty");
+        EnvironmentMap {image, intensity, rotation}
+    }
+
+    pub fn color(&self, direction: Vector3) -> RGBColor {
+        let d = direction.unit();
+        let phi = f64::atan2(d.x, -d.z) - self.rotation.to_radians();
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = f64::acos(clamp(d.y, -1.0, 1.0)) / PI;
+
+        self.bilinear(u, v) * self.intensity
+    }
+
+    // wraps around horizontally and clamps at the poles
+    fn bilinear(&self, u: f64, v: f64) -> RGBColor {
+        let width = self.image.width();
+        let height = self.image.height();
+        let x = u * width as f64 - 0.5;
+        let y = clamp(v * height as f64 - 0.5, 0.0, (height - 1) as f64);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let column = |i: f64| (i as i64).rem_euclid(width as i64) as usize;
+        let (xa, xb) = (column(x0), column(x0 + 1.0));
+        let (ya, yb) = (y0 as usize, usize::min(y0 as usize + 1, height - 1));
+
+        let top = self.image.get(xa, ya) * (1.0 - fx) + self.image.get(xb, ya) * fx;
+        let bottom = self.image.get(xa, yb) * (1.0 - fx) + self.image.get(xb, yb) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// Perez sky luminance distribution for one of Y, x or y
+fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = coefficients;
+    (1.0 + a * f64::exp(b / cos_theta)) * (1.0 + c * f64::exp(d * gamma) + e * gamma.cos().powi(2))
+}
+
+// clear sky from Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight" (1999).
+// It doesn't include the sun disk itself, and directions below the horizon see the horizon
+// color times `ground`
+#[derive(Clone)]
+pub struct Sky {
+    sun_direction: Vector3,
+    // luminance is in kcd/m², scaled by this factor
+    intensity: f64,
+    ground: RGBColor,
+    coefficients: [[f64; 5]; 3],
+    // zenith value of Y, x and y divided by the Perez function at the zenith
+    zenith: [f64; 3],
+}
+
+impl Sky {
+    // `turbidity` goes from about 2 for a very clear sky to 10 for a hazy one
+    pub fn new(sun_direction: Vector3, turbidity: f64, intensity: f64, ground: RGBColor) -> Self {
+        let t = turbidity;
+        let sun = sun_direction.unit();
+        // the model only covers suns above the horizon
+        let theta_s = f64::acos(clamp(sun.y, 0.0, 1.0));
+
+        let coefficients = [
+            [0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703],
+            [-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452],
+            [-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529],
+        ];
+
+        let chi = (4.0/9.0 - t/120.0) * (PI - 2.0*theta_s);
+        let zenith_luminance = (4.0453*t - 4.9710) * chi.tan() - 0.2155*t + 2.4192;
+        let (t2, s, s2, s3) = (t*t, theta_s, theta_s*theta_s, theta_s*theta_s*theta_s);
+        let zenith_x = t2 * (0.00166*s3 - 0.00375*s2 + 0.00209*s)
+            + t * (-0.02903*s3 + 0.06377*s2 - 0.03202*s + 0.00394)
+            + (0.11693*s3 - 0.21196*s2 + 0.06052*s + 0.25886);
+        let zenith_y = t2 * (0.00275*s3 - 0.00610*s2 + 0.00317*s)
+            + t * (-0.04214*s3 + 0.08970*s2 - 0.04153*s + 0.00516)
+            + (0.15346*s3 - 0.26756*s2 + 0.06670*s + 0.26688);
+
+        let zenith_values = [zenith_luminance, zenith_x, zenith_y];
+        let mut zenith = [0.0; 3];
+        for i in 0..3 {
+            zenith[i] = zenith_values[i] / perez(&coefficients[i], 1.0, theta_s);
+        }
+
+        Sky {sun_direction: sun, intensity, ground, coefficients, zenith}
+    }
+
+    pub fn sun_direction(&self) -> Vector3 {
+        self.sun_direction
+    }
+
+    pub fn color(&self, direction: Vector3) -> RGBColor {
+        let d = direction.unit();
+        // just above the horizon, where the Perez function still behaves
+        let cos_theta = f64::max(d.y, 0.01);
+        let gamma = f64::acos(clamp(d.dot(self.sun_direction), -1.0, 1.0));
+
+        let luminance = self.zenith[0] * perez(&self.coefficients[0], cos_theta, gamma);
+        let x = self.zenith[1] * perez(&self.coefficients[1], cos_theta, gamma);
+        let y = self.zenith[2] * perez(&self.coefficients[2], cos_theta, gamma);
+
+        // xyY to XYZ to linear sRGB
+        let big_x = x / y * luminance;
+        let big_z = (1.0 - x - y) / y * luminance;
+        let rgb = RGBColor::from_xyz(big_x, luminance, big_z);
+        let color = RGBColor::new(rgb.r().max(0.0), rgb.g().max(0.0), rgb.b().max(0.0)) * self.intensity;
+
+        match d.y < 0.0 {
+            true => color * self.ground,
+            false => color,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{Background, EnvironmentMap, Sky};
+    use crate::{color::RGBColor, framebuffer::Framebuffer, vector3::Vector3};
+
+    #[test]
+    fn gradient_test() {
+        let background = Background::default();
+
+        assert_eq!(RGBColor::new(0.5, 0.7, 1.0), background.color(Vector3::new(0.0, 2.0, 0.0)));
+        assert_eq!(RGBColor::new(0.75, 0.85, 1.0), background.color(Vector3::new(1.0, 0.0, 0.0)));
+    }
+    #[test]
+    fn environment_test() {
+        // left half red, right half blue
+        let mut image = Framebuffer::new(4, 2);
+        for y in 0..2 {
+            for x in 0..4 {
+                image.set(x, y, match x < 2 {
+                    true => RGBColor::new(1.0, 0.0, 0.0),
+                    false => RGBColor::new(0.0, 0.0, 1.0),
+                });
+            }
+        }
+        let background = Background::Environment(Arc::new(EnvironmentMap::new(image, 2.0, 0.0)));
+
+        // -x is a quarter of the way across the image, +x three quarters
+        assert_eq!(RGBColor::new(2.0, 0.0, 0.0), background.color(Vector3::new(-1.0, 0.0, 0.0)));
+        assert_eq!(RGBColor::new(0.0, 0.0, 2.0), background.color(Vector3::new(1.0, 0.0, 0.0)));
+    }
+    #[test]
+    fn sky_test() {
+        let sky = Sky::new(Vector3::new(0.0, 1.0, -1.0), 2.5, 0.1, RGBColor::new(0.3, 0.3, 0.3));
+        let zenith = sky.color(Vector3::new(0.0, 1.0, 0.0));
+        let near_sun = sky.color(Vector3::new(0.0, 1.0, -1.1));
+
+        // blue sky overhead, brighter around the sun
+        assert!(zenith.b() > zenith.r() && zenith.r() > 0.0);
+        assert!(near_sun.g() > zenith.g());
+        // the zenith luminance of the model is about 5.9 kcd/m² for this sun
+        assert!((0.4..0.8).contains(&zenith.luminance()));
+    }
+}
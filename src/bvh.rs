@@ -0,0 +1,220 @@
+use crate::{aabb::Aabb, hittable::HitRecord, ray::Ray, vector3::Vector3};
+
+const SAH_BINS: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+// relative cost of testing a node's box against intersecting a primitive
+const TRAVERSAL_COST: f64 = 0.125;
+
+#[derive(Clone, Copy)]
+struct BvhNode {
+    bounds: Aabb,
+    // first primitive for leaves, index of the left child for interior nodes
+    // (the right child is always stored right after the left one)
+    first: usize,
+    // number of primitives, 0 for interior nodes
+    count: usize,
+    axis: usize,
+}
+
+// bounding volume hierarchy over a list of primitives, built with the surface area heuristic.
+// It only stores indices into the caller's primitive list, so the same structure is used by
+// the world and by meshes
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    indices: Vec<usize>,
+}
+
+#[derive(Clone, Copy)]
+struct Bin {
+    bounds: Aabb,
+    count: usize,
+}
+
+impl Bvh {
+    pub fn new(bounds: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if bounds.is_empty() {
+            return bvh
+        }
+        let centroids: Vec<Vector3> = bounds.iter().map(|b| b.centroid()).collect();
+
+        bvh.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: bounds.len(), axis: 0 });
+        bvh.subdivide(0, bounds, &centroids);
+        bvh
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        match self.nodes.first() {
+            Some(root) => root.bounds,
+            None => Aabb::empty(),
+        }
+    }
+
+    fn subdivide(&mut self, node_index: usize, bounds: &[Aabb], centroids: &[Vector3]) {
+        let first = self.nodes[node_index].first;
+        let count = self.nodes[node_index].count;
+        let prims = &self.indices[first..first + count];
+
+        let node_bounds = prims.iter().fold(Aabb::empty(), |acc, &i| acc.union(&bounds[i]));
+        let centroid_bounds = prims.iter().fold(Aabb::empty(), |acc, &i| acc.grow(centroids[i]));
+        self.nodes[node_index].bounds = node_bounds;
+
+        if count <= 1 {
+            return
+        }
+
+        let (axis, split, split_cost) = match Bvh::find_split(prims, bounds, centroids, &centroid_bounds) {
+            Some(s) => s,
+            None => return, // all centroids coincide, nothing to split
+        };
+        let leaf_cost = count as f64;
+        if count <= MAX_LEAF_SIZE && split_cost / node_bounds.surface_area() + TRAVERSAL_COST >= leaf_cost {
+            return
+        }
+
+        // partition the primitives in place around the chosen bin boundary
+        let min = centroid_bounds.min[axis];
+        let scale = SAH_BINS as f64 / (centroid_bounds.max[axis] - min);
+        let prims = &mut self.indices[first..first + count];
+        let mut left_count = 0;
+        for i in 0..count {
+            if Bvh::bin_index(centroids[prims[i]][axis], min, scale) < split {
+                prims.swap(i, left_count);
+                left_count += 1;
+            }
+        }
+        if left_count == 0 || left_count == count {
+            return
+        }
+
+        let left = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first, count: left_count, axis: 0 });
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: first + left_count, count: count - left_count, axis: 0 });
+        self.nodes[node_index].first = left;
+        self.nodes[node_index].count = 0;
+        self.nodes[node_index].axis = axis;
+
+        self.subdivide(left, bounds, centroids);
+        self.subdivide(left + 1, bounds, centroids);
+    }
+
+    fn bin_index(centroid: f64, min: f64, scale: f64) -> usize {
+        usize::min(((centroid - min) * scale) as usize, SAH_BINS - 1)
+    }
+
+    // returns the axis, the first bin of the right side and the (unnormalized) cost of the best split
+    fn find_split(prims: &[usize], bounds: &[Aabb], centroids: &[Vector3], centroid_bounds: &Aabb) -> Option<(usize, usize, f64)> {
+        let mut best: Option<(usize, usize, f64)> = None;
+
+        for axis in 0..3 {
+            let min = centroid_bounds.min[axis];
+            let max = centroid_bounds.max[axis];
+            if max <= min {
+                continue;
+            }
+            let scale = SAH_BINS as f64 / (max - min);
+
+            let mut bins = [Bin { bounds: Aabb::empty(), count: 0 }; SAH_BINS];
+            for &i in prims {
+                let b = &mut bins[Bvh::bin_index(centroids[i][axis], min, scale)];
+                b.count += 1;
+                b.bounds = b.bounds.union(&bounds[i]);
+            }
+
+            // sweep from the right to get the cost of every right side, then from the left
+            let mut right_cost = [0.0; SAH_BINS];
+            let mut acc = Bin { bounds: Aabb::empty(), count: 0 };
+            for split in (1..SAH_BINS).rev() {
+                acc.count += bins[split].count;
+                acc.bounds = acc.bounds.union(&bins[split].bounds);
+                right_cost[split] = acc.count as f64 * acc.bounds.surface_area();
+            }
+            let mut acc = Bin { bounds: Aabb::empty(), count: 0 };
+            for split in 1..SAH_BINS {
+                acc.count += bins[split - 1].count;
+                acc.bounds = acc.bounds.union(&bins[split - 1].bounds);
+                let cost = acc.count as f64 * acc.bounds.surface_area() + right_cost[split];
+                if best.is_none_or(|(_, _, c)| cost < c) {
+                    best = Some((axis, split, cost));
+                }
+            }
+        }
+        best
+    }
+
+    // finds the closest hit along the ray and the primitive it belongs to; `hit_primitive` is
+    // called with the index of a primitive and the current closest distance. Ties in distance
+    // go to the primitive with the highest index, which is what a linear scan over the list
+    // would return
+    pub fn hit<'a, F>(&self, ray: &Ray, t_min: f64, t_max: f64, mut hit_primitive: F) -> Option<(HitRecord<'a>, usize)>
+    where F: FnMut(usize, f64) -> Option<HitRecord<'a>> {
+        if self.nodes.is_empty() {
+            return None
+        }
+        let inv_dir = Vector3::new(1.0/ray.direction.x, 1.0/ray.direction.y, 1.0/ray.direction.z);
+        self.nodes[0].bounds.hit(ray, inv_dir, t_min, t_max)?;
+
+        let mut closest: Option<(HitRecord<'a>, usize)> = None;
+        let mut closest_so_far = t_max;
+        let mut stack: Vec<usize> = Vec::with_capacity(64);
+        stack.push(0);
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds.hit(ray, inv_dir, t_min, closest_so_far).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for &prim in &self.indices[node.first..node.first + node.count] {
+                    if let Some(rec) = hit_primitive(prim, closest_so_far) {
+                        let replaces = match &closest {
+                            Some((best, best_prim)) => rec.t < best.t || (rec.t == best.t && prim > *best_prim),
+                            None => true,
+                        };
+                        if replaces {
+                            closest_so_far = rec.t;
+                            closest = Some((rec, prim));
+                        }
+                    }
+                }
+            } else {
+                // visit the child on the ray's side of the split first
+                let (near, far) = match ray.direction[node.axis] < 0.0 {
+                    true => (node.first + 1, node.first),
+                    false => (node.first, node.first + 1),
+                };
+                stack.push(far);
+                stack.push(near);
+            }
+        }
+
+        closest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Bvh;
+    use crate::{aabb::Aabb, vector3::Vector3};
+
+    #[test]
+    fn bounds_test() {
+        let boxes: Vec<Aabb> = (0..100)
+            .map(|i| {
+                let c = Vector3::new(i as f64, (i % 7) as f64, -(i % 3) as f64);
+                Aabb::new(c - Vector3::new(0.5, 0.5, 0.5), c + Vector3::new(0.5, 0.5, 0.5))
+            })
+            .collect();
+        let bvh = Bvh::new(&boxes);
+        let res = Aabb::new(Vector3::new(-0.5, -0.5, -2.5), Vector3::new(99.5, 6.5, 0.5));
+
+        assert_eq!(res, bvh.bounding_box());
+
+        let mut indices = bvh.indices.clone();
+        indices.sort();
+        assert_eq!((0..100).collect::<Vec<usize>>(), indices);
+    }
+}
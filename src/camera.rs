@@ -1,6 +1,6 @@
 use fastrand::Rng;
 
-use crate::{vector3::Vector3, ray::Ray, utils::{random_vec_in_unit_sphere, random_vec_in_unit_disk}};
+use crate::{vector3::Vector3, ray::Ray, utils::random_vec_in_unit_disk};
 pub struct Camera {
     origin: Vector3,
     horizontal: Vector3,
@@ -9,7 +9,9 @@ pub struct Camera {
     lens_radius: f64,
     u: Vector3,
     v: Vector3,
-    w: Vector3,
+    // interval the rays' times are picked in, a single instant by default
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
@@ -43,16 +45,56 @@ impl Camera {
             lens_radius: aperture / 2.0,
             u,
             v,
-            w
+            shutter_open: 0.0,
+            shutter_close: 0.0,
         }
     }
 
+    // rays are spread evenly over the times from `open` to `close`, blurring moving shapes
+    pub fn set_shutter(&mut self, open: f64, close: f64) {
+        self.shutter_open = open;
+        self.shutter_close = close;
+    }
+
+    // widens or narrows the view for images of another width / height ratio, keeping the
+    // vertical field of view
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        let horizontal = self.u * (self.vertical.length() * aspect_ratio);
+        self.lower_left_corner = self.lower_left_corner + (self.horizontal - horizontal) / 2.0;
+        self.horizontal = horizontal;
+    }
+
     pub fn get_ray(&self, s: f64, t: f64, rng: &Rng) -> Ray{
         let rd = random_vec_in_unit_disk(rng) * self.lens_radius;
         let offset = self.u * rd.x + self.v * rd.y;
 
         let dir = self.lower_left_corner + self.horizontal*s + self.vertical*t - self.origin;
 
-        Ray::new(self.origin + offset, dir - offset)
+        // no random number is drawn for a single instant, so still images stay the same
+        let time = match self.shutter_close > self.shutter_open {
+            true => self.shutter_open + rng.f64() * (self.shutter_close - self.shutter_open),
+            false => self.shutter_open,
+        };
+        Ray::with_time(self.origin + offset, dir - offset, time)
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::Camera;
+    use crate::vector3::Vector3;
+
+    #[test]
+    fn aspect_ratio_test() {
+        let new = |aspect_ratio| Camera::new(Vector3::new(1.0, 2.0, 3.0), Vector3::new(0.0, 0.5, 0.0),
+                                             Vector3::new(0.0, 1.0, 0.0), 30.0, aspect_ratio, 0.0, 2.0);
+        let mut camera = new(1.5);
+        camera.set_aspect_ratio(0.5);
+        let expected = new(0.5);
+        let rng = fastrand::Rng::with_seed(1);
+
+        for (s, t) in [(0.0, 0.0), (0.3, 0.9), (1.0, 1.0)] {
+            let d = camera.get_ray(s, t, &rng).direction - expected.get_ray(s, t, &rng).direction;
+            assert!(d.length() < 1e-9);
+        }
     }
-}
\ No newline at end of file
+}
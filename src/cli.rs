@@ -0,0 +1,192 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rust_ray_tracer::{image_writer::ImageFormat, render::RenderSettings};
+
+pub const USAGE: &str = "\
+Usage: rust-ray-tracer render [SCENE] [OPTIONS]
+
+Renders SCENE, a .toml scene file, or the random spheres scene when none is given.
+Options override the values of the scene's [render] section.
+
+Options:
+      --width <PIXELS>        image width, the height follows the scene's aspect ratio
+      --height <PIXELS>       image height, the width follows the scene's aspect ratio
+      --spp <SAMPLES>         samples per pixel
+      --max-depth <BOUNCES>   maximum number of bounces of a path
+      --seed <SEED>           seed of the random numbers
+      --threads <COUNT>       number of render threads, all cores by default
+      --spectral              trace wavelengths of light rather than rgb colors, for dispersion
+  -o, --output <FILE>         image to write [default: image.ppm]
+      --format <FORMAT>       ppm, png, hdr, pfm or exr, from the output extension by default
+  -h, --help                  print this help
+";
+
+// options of the render command besides --help and --spectral, all of which take a value
+const OPTIONS: [&str; 9] = ["--width", "--height", "--spp", "--max-depth", "--seed", "--threads", "-o", "--output", "--format"];
+
+#[derive(Debug,PartialEq)]
+pub enum Command {
+    Help,
+    Render(RenderOptions),
+}
+
+#[derive(Debug,PartialEq)]
+pub struct RenderOptions {
+    pub scene: Option<PathBuf>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub samples_per_pixel: Option<i32>,
+    pub max_depth: Option<i32>,
+    pub seed: Option<u64>,
+    pub threads: Option<usize>,
+    pub spectral: bool,
+    pub output: PathBuf,
+    pub format: ImageFormat,
+}
+
+impl RenderOptions {
+    // replaces the settings given on the command line, keeping the aspect ratio when only one
+    // of the width and height is
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        let aspect_ratio = settings.aspect_ratio();
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => (settings.width, settings.height) = (width, height),
+            (Some(width), None) => (settings.width, settings.height) = (width, usize::max(1, (width as f64 / aspect_ratio) as usize)),
+            (None, Some(height)) => (settings.width, settings.height) = (usize::max(1, (height as f64 * aspect_ratio) as usize), height),
+            (None, None) => (),
+        }
+        settings.samples_per_pixel = self.samples_per_pixel.unwrap_or(settings.samples_per_pixel);
+        settings.max_depth = self.max_depth.unwrap_or(settings.max_depth);
+        settings.seed = self.seed.unwrap_or(settings.seed);
+        settings.threads = self.threads.unwrap_or(settings.threads);
+        settings.spectral |= self.spectral;
+    }
+}
+
+// value of a numeric option, at least 1 unless it's the seed
+fn number<T: FromStr + PartialOrd + From<u8>>(option: &str, value: &str) -> Result<T, String> {
+    let min = match option {
+        "--seed" => T::from(0),
+        _ => T::from(1),
+    };
+    match value.parse::<T>() {
+        Ok(n) if n >= min => Ok(n),
+        _ => Err(format!("invalid value `{}` for {}", value, option)),
+    }
+}
+
+// `args` doesn't include the program name. No arguments at all renders the default scene
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter();
+    match args.next().map(|a| a.as_str()) {
+        None | Some("render") => (),
+        Some("-h") | Some("--help") | Some("help") => return Ok(Command::Help),
+        Some(command) => return Err(format!("unknown command `{}`", command)),
+    }
+
+    let mut options = RenderOptions {
+        scene: None,
+        width: None,
+        height: None,
+        samples_per_pixel: None,
+        max_depth: None,
+        seed: None,
+        threads: None,
+        spectral: false,
+        output: PathBuf::from("image.ppm"),
+        format: ImageFormat::Ppm,
+    };
+    let mut format = None;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            if options.scene.is_some() {
+                return Err(format!("unexpected argument `{}`, only one scene can be rendered", arg))
+            }
+            options.scene = Some(PathBuf::from(arg));
+            continue;
+        }
+        // both `--option value` and `--option=value`
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) => (option, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if option == "-h" || option == "--help" {
+            return Ok(Command::Help)
+        }
+        if option == "--spectral" {
+            if inline_value.is_some() {
+                return Err("--spectral takes no value".to_string())
+            }
+            options.spectral = true;
+            continue;
+        }
+        if !OPTIONS.contains(&option) {
+            return Err(format!("unknown option `{}`", option))
+        }
+        let value = inline_value.or_else(|| args.next().cloned())
+            .ok_or_else(|| format!("missing value for {}", option))?;
+        match option {
+            "--width" => options.width = Some(number(option, &value)?),
+            "--height" => options.height = Some(number(option, &value)?),
+            "--spp" => options.samples_per_pixel = Some(number(option, &value)?),
+            "--max-depth" => options.max_depth = Some(number(option, &value)?),
+            "--seed" => options.seed = Some(number(option, &value)?),
+            "--threads" => options.threads = Some(number(option, &value)?),
+            "-o" | "--output" => options.output = PathBuf::from(value),
+            "--format" => format = Some(ImageFormat::from_name(&value)
+                .ok_or_else(|| format!("unknown image format `{}`, expected ppm, png, hdr, pfm or exr", value))?),
+            _ => unreachable!(),
+        }
+    }
+
+    options.format = match format.or_else(|| ImageFormat::from_extension(&options.output)) {
+        Some(format) => format,
+        None => return Err(format!("can't tell the image format of `{}`, use --format", options.output.display())),
+    };
+    Ok(Command::Render(options))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use super::{parse, Command};
+    use rust_ray_tracer::{image_writer::ImageFormat, render::RenderSettings};
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_test() {
+        let options = match parse(&args("render scenes/cornell.toml --width 200 --spp=16 --seed 0 -o out.exr")) {
+            Ok(Command::Render(options)) => options,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(Some(PathBuf::from("scenes/cornell.toml")), options.scene);
+        assert_eq!((Some(200), None, Some(16), Some(0)), (options.width, options.height, options.samples_per_pixel, options.seed));
+        assert_eq!(ImageFormat::Exr, options.format);
+
+        // the height keeps the aspect ratio of the scene
+        let mut settings = RenderSettings::with_aspect_ratio(400, 2.0);
+        options.apply(&mut settings);
+        assert_eq!((200, 100, 16), (settings.width, settings.height, settings.samples_per_pixel));
+
+        assert!(matches!(parse(&[]), Ok(Command::Render(_))));
+        assert_eq!(Ok(Command::Help), parse(&args("render --help")));
+        assert!(matches!(parse(&args("render -o image --format PNG")), Ok(Command::Render(o)) if o.format == ImageFormat::Png));
+        assert!(matches!(parse(&args("render --spectral --seed 1")), Ok(Command::Render(o)) if o.spectral && o.seed == Some(1)));
+    }
+    #[test]
+    fn error_test() {
+        assert_eq!(Err("unknown command `draw`".to_string()), parse(&args("draw")));
+        assert_eq!(Err("invalid value `0` for --spp".to_string()), parse(&args("render --spp 0")));
+        assert_eq!(Err("invalid value `wide` for --width".to_string()), parse(&args("render --width wide")));
+        assert_eq!(Err("missing value for --threads".to_string()), parse(&args("render --threads")));
+        assert_eq!(Err("unknown option `--fast`".to_string()), parse(&args("render --fast")));
+        assert_eq!(Err("--spectral takes no value".to_string()), parse(&args("render --spectral=true")));
+        assert_eq!(Err("can't tell the image format of `out.tiff`, use --format".to_string()), parse(&args("render -o out.tiff")));
+        assert!(parse(&args("render a.toml b.toml")).is_err());
+    }
+}
@@ -1,8 +1,6 @@
-use std::fs::File;
-use std::io::Write;
 use fastrand::Rng;
 
-use crate::utils::{clamp};
+use crate::utils::clamp;
 #[derive(Debug,PartialEq,Clone,Copy)]
 pub struct RGBColor {
     r: f64,
@@ -13,18 +11,61 @@ pub struct RGBColor {
 
 
 impl RGBColor {
-    pub fn new(r: f64, g: f64, b: f64) -> Self {
+    pub const fn new(r: f64, g: f64, b: f64) -> Self {
         RGBColor {r, g, b}
     }
-    pub fn write_color(&self, f: &mut File, samples_per_pixel: i32) {
-        let scale = 1.0 / samples_per_pixel as f64;
-
-        let r= (256.0 * clamp(f64::sqrt(self.r * scale), 0.0, 0.999)).round() as i32;
-        let g = (256.0 * clamp(f64::sqrt(self.g * scale), 0.0, 0.999)).round() as i32;
-        let b = (256.0 * clamp(f64::sqrt(self.b * scale), 0.0, 0.999)).round() as i32;
+    pub fn r(&self) -> f64 {
+        self.r
+    }
+    pub fn g(&self) -> f64 {
+        self.g
+    }
+    pub fn b(&self) -> f64 {
+        self.b
+    }
+    // relative luminance of linear sRGB
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+    // gamma corrected (gamma 2) 8-bit values, for display formats
+    pub fn to_rgb8(&self) -> [u8; 3] {
+        let encode = |c: f64| (256.0 * clamp(f64::sqrt(c), 0.0, 0.999)) as u8;
 
+        [encode(self.r), encode(self.g), encode(self.b)]
+    }
 
-        write!(f, "{} {} {}\n", r, g, b).expect("failed to write to data");
+    // linear sRGB of a CIE XYZ color, negative outside of the sRGB gamut
+    pub fn from_xyz(x: f64, y: f64, z: f64) -> RGBColor {
+        RGBColor::new(
+            3.2406 * x - 1.5372 * y - 0.4986 * z,
+            -0.9689 * x + 1.8758 * y + 0.0415 * z,
+            0.0557 * x - 0.2040 * y + 1.0570 * z,
+        )
+    }
+    // color of the light a black body radiates at a temperature, with a luminance of 1
+    pub fn blackbody(kelvin: f64) -> RGBColor {
+        // relative to the radiance at 780 nm, which doesn't underflow for cold bodies
+        const C2: f64 = 1.438777e7;
+        let relative = |wavelength: f64| {
+            let (x, x_ref) = (C2 / (wavelength * kelvin), C2 / (780.0 * kelvin));
+            let ratio = match x > 50.0 {
+                true => f64::exp(x_ref - x),
+                false => x_ref.exp_m1() / x.exp_m1(),
+            };
+            (780.0 / wavelength).powi(5) * ratio
+        };
+        let mut xyz = [0.0; 3];
+        for step in 0..=80 {
+            let wavelength = 380.0 + 5.0 * step as f64;
+            let radiance = relative(wavelength);
+            for (sum, cmf) in xyz.iter_mut().zip(cie_xyz(wavelength)) {
+                *sum += radiance * cmf;
+            }
+        }
+        let rgb = RGBColor::from_xyz(xyz[0], xyz[1], xyz[2]);
+        // the reddest temperatures are outside the gamut
+        let rgb = RGBColor::new(rgb.r.max(0.0), rgb.g.max(0.0), rgb.b.max(0.0));
+        rgb * (1.0 / rgb.luminance())
     }
 
     pub fn random(rng: &Rng) -> RGBColor{
@@ -39,6 +80,20 @@ impl RGBColor {
     }
 }
 
+// CIE 1931 color matching functions at a wavelength in nanometers, from the multi-lobe fit of
+// Wyman, Sloan and Shirley
+pub fn cie_xyz(wavelength: f64) -> [f64; 3] {
+    let lobe = |mean: f64, below: f64, above: f64| {
+        let t = (wavelength - mean) / if wavelength < mean { below } else { above };
+        f64::exp(-0.5 * t * t)
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7) - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
+}
+
 impl std::ops::Mul<f64> for RGBColor {
     type Output = RGBColor;
     fn mul(self, rhs: f64) -> Self::Output {
@@ -69,3 +124,23 @@ impl std::ops::Add<RGBColor> for RGBColor {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{cie_xyz, RGBColor};
+
+    #[test]
+    fn blackbody_test() {
+        // the matching functions peak about where they should
+        assert!((cie_xyz(555.0)[1] - 1.0).abs() < 0.02);
+        assert!(cie_xyz(700.0)[0] > cie_xyz(700.0)[2]);
+
+        let glow = RGBColor::blackbody(1000.0);
+        assert!((glow.luminance() - 1.0).abs() < 1e-9);
+        assert!(glow.r() > glow.g() && glow.g() >= glow.b());
+        // hotter is whiter
+        let white = RGBColor::blackbody(6500.0);
+        assert!((white.r() / white.b() - 1.0).abs() < 0.1, "{:?}", white);
+        assert!(RGBColor::blackbody(10.0).luminance().is_finite());
+    }
+}
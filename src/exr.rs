@@ -0,0 +1,294 @@
+use std::io::{self, Write};
+
+use crate::{framebuffer::Framebuffer, image_writer::{check_not_empty, ImageWriter}};
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ExrCompression {
+    None,
+    // zlib over blocks of 16 scanlines
+    Zip,
+}
+
+// scanline OpenEXR file holding the beauty pass as R, G and B plus every output variable
+// channel of the framebuffer
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct ExrWriter {
+    pub pixel_type: ExrPixelType,
+    pub compression: ExrCompression,
+}
+
+impl Default for ExrWriter {
+    fn default() -> Self {
+        ExrWriter { pixel_type: ExrPixelType::Half, compression: ExrCompression::Zip }
+    }
+}
+
+// rounds to the nearest half float, ties to even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // infinity stays infinity, nan keeps a mantissa bit set
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 }
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        // too large, overflows to infinity
+        return sign | 0x7c00
+    }
+    if half_exponent <= 0 {
+        // subnormal half, or zero when even the rounding can't reach the smallest subnormal
+        if half_exponent < -10 {
+            return sign
+        }
+        let m = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = m >> shift;
+        let rest = m & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round_up = rest > halfway || (rest == halfway && half_mantissa & 1 == 1);
+        return sign | (half_mantissa + round_up as u32) as u16
+    }
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    let rest = mantissa & 0x1fff;
+    let round_up = rest > 0x1000 || (rest == 0x1000 && half & 1 == 1);
+    // a carry out of the mantissa correctly bumps the exponent, up to infinity
+    sign | (half + round_up as u32) as u16
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: usize, height: usize) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1].iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+// byte reordering and delta predictor applied before zlib, as done by the OpenEXR library
+pub fn zip_predict(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    out.extend(raw.iter().step_by(2));
+    out.extend(raw.iter().skip(1).step_by(2));
+
+    let mut previous = out.first().copied().unwrap_or(0);
+    for byte in out.iter_mut().skip(1) {
+        let current = *byte;
+        *byte = (current as i32 - previous as i32 + 128 + 256) as u8;
+        previous = current;
+    }
+    out
+}
+
+impl ExrWriter {
+    fn lines_per_block(&self) -> usize {
+        match self.compression {
+            ExrCompression::None => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+}
+
+impl ImageWriter for ExrWriter {
+    fn write(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        check_not_empty(image)?;
+        let width = image.width();
+        let height = image.height();
+
+        // the beauty pass plus the output variables, sorted by name as the format requires
+        let mut channels: Vec<(String, Vec<f32>)> = vec![
+            ("R".to_string(), image.pixels().iter().map(|c| c.r() as f32).collect()),
+            ("G".to_string(), image.pixels().iter().map(|c| c.g() as f32).collect()),
+            ("B".to_string(), image.pixels().iter().map(|c| c.b() as f32).collect()),
+        ];
+        for (name, data) in image.aovs() {
+            channels.push((name.to_string(), data.to_vec()));
+        }
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut header = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+        let mut chlist = Vec::new();
+        for (name, _) in &channels {
+            chlist.extend_from_slice(name.as_bytes());
+            chlist.push(0);
+            let pixel_type: i32 = match self.pixel_type {
+                ExrPixelType::Half => 1,
+                ExrPixelType::Float => 2,
+            };
+            chlist.extend_from_slice(&pixel_type.to_le_bytes());
+            // linear flag and reserved bytes, then x and y sampling
+            chlist.extend_from_slice(&[0, 0, 0, 0]);
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+            chlist.extend_from_slice(&1i32.to_le_bytes());
+        }
+        chlist.push(0);
+        write_attribute(&mut header, "channels", "chlist", &chlist);
+        let compression = match self.compression {
+            ExrCompression::None => 0,
+            ExrCompression::Zip => 3,
+        };
+        write_attribute(&mut header, "compression", "compression", &[compression]);
+        write_attribute(&mut header, "dataWindow", "box2i", &box2i(width, height));
+        write_attribute(&mut header, "displayWindow", "box2i", &box2i(width, height));
+        write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        write_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+        write_attribute(&mut header, "screenWindowCenter", "v2f", &[0u8; 8]);
+        write_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+        header.push(0);
+
+        // every block stores its scanlines one after the other, each scanline holding all the
+        // values of the first channel, then of the second and so on
+        let lines = self.lines_per_block();
+        let mut blocks = Vec::new();
+        for first_line in (0..height).step_by(lines) {
+            let mut raw = Vec::new();
+            for y in first_line..usize::min(first_line + lines, height) {
+                for (_, data) in &channels {
+                    for &value in &data[y * width..(y + 1) * width] {
+                        match self.pixel_type {
+                            ExrPixelType::Half => raw.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+                            ExrPixelType::Float => raw.extend_from_slice(&value.to_le_bytes()),
+                        }
+                    }
+                }
+            }
+            let data = match self.compression {
+                ExrCompression::None => raw,
+                ExrCompression::Zip => {
+                    let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&zip_predict(&raw), 6);
+                    // readers take blocks that didn't shrink as uncompressed
+                    match compressed.len() < raw.len() {
+                        true => compressed,
+                        false => raw,
+                    }
+                }
+            };
+            let mut block = Vec::with_capacity(data.len() + 8);
+            block.extend_from_slice(&(first_line as i32).to_le_bytes());
+            block.extend_from_slice(&(data.len() as i32).to_le_bytes());
+            block.extend_from_slice(&data);
+            blocks.push(block);
+        }
+
+        // offset table with the position of every block in the file
+        let mut offset = (header.len() + 8 * blocks.len()) as u64;
+        for block in &blocks {
+            header.extend_from_slice(&offset.to_le_bytes());
+            offset += block.len() as u64;
+        }
+        out.write_all(&header)?;
+        for block in &blocks {
+            out.write_all(block)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{f32_to_f16, zip_predict, ExrCompression, ExrPixelType, ExrWriter};
+    use crate::{color::RGBColor, framebuffer::Framebuffer, image_writer::ImageWriter};
+
+    fn read_i32(data: &[u8], at: usize) -> i32 {
+        i32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+    fn undo_predict(data: &[u8]) -> Vec<u8> {
+        let mut t = data.to_vec();
+        for i in 1..t.len() {
+            t[i] = (t[i - 1] as i32 + t[i] as i32 - 128) as u8;
+        }
+        let half = t.len().div_ceil(2);
+        let mut raw = Vec::new();
+        for i in 0..t.len() {
+            raw.push(match i % 2 {
+                0 => t[i / 2],
+                _ => t[half + i / 2],
+            });
+        }
+        raw
+    }
+
+    #[test]
+    fn f16_test() {
+        assert_eq!(0x3c00, f32_to_f16(1.0));
+        assert_eq!(0xc000, f32_to_f16(-2.0));
+        assert_eq!(0x7bff, f32_to_f16(65504.0));
+        assert_eq!(0x7c00, f32_to_f16(1e6));
+        assert_eq!(0x7c00, f32_to_f16(f32::INFINITY));
+        assert_eq!(0x0001, f32_to_f16(5.9604645e-8));
+        assert_eq!(0x3555, f32_to_f16(1.0 / 3.0));
+        assert_eq!(0, f32_to_f16(1e-10));
+    }
+    #[test]
+    fn predict_test() {
+        let raw: Vec<u8> = (0..37u8).map(|i| i.wrapping_mul(71)).collect();
+
+        assert_eq!(raw, undo_predict(&zip_predict(&raw)));
+    }
+    #[test]
+    fn exr_layout_test() {
+        let mut image = Framebuffer::new(3, 20);
+        image.set(1, 0, RGBColor::new(0.5, 1.0, 2.0));
+        image.add_aov("Z");
+        image.set_aov("Z", 2, 19, 7.0);
+
+        for compression in [ExrCompression::None, ExrCompression::Zip] {
+            let mut out = Vec::new();
+            ExrWriter { pixel_type: ExrPixelType::Float, compression }.write(&image, &mut out).unwrap();
+
+            assert_eq!([0x76, 0x2f, 0x31, 0x01], out[0..4]);
+            assert_eq!(b"channels\0chlist\0", &out[8..24]);
+
+            let blocks = match compression {
+                ExrCompression::None => 20,
+                ExrCompression::Zip => 2,
+            };
+            // the first offset points right after the offset table, at the first block
+            let table = find_table(&out);
+            let first = u64::from_le_bytes(out[table..table + 8].try_into().unwrap()) as usize;
+            assert_eq!(table + 8 * blocks, first);
+            assert_eq!(0, read_i32(&out, first));
+
+            let size = read_i32(&out, first + 4) as usize;
+            let data = &out[first + 8..first + 8 + size];
+            let raw = match compression {
+                ExrCompression::None => data.to_vec(),
+                ExrCompression::Zip => undo_predict(&miniz_oxide::inflate::decompress_to_vec_zlib(data).unwrap()),
+            };
+            // channels are sorted as B, G, R, Z within the first scanline
+            let value = |channel: usize, x: usize| f32::from_le_bytes(raw[4 * (channel * 3 + x)..4 * (channel * 3 + x) + 4].try_into().unwrap());
+            assert_eq!((2.0, 1.0, 0.5, 0.0), (value(0, 1), value(1, 1), value(2, 1), value(3, 1)));
+        }
+
+        // an empty data window can't be described
+        let mut out = Vec::new();
+        let err = ExrWriter { pixel_type: ExrPixelType::Half, compression: ExrCompression::Zip }.write(&Framebuffer::new(0, 4), &mut out).unwrap_err();
+        assert_eq!(std::io::ErrorKind::InvalidInput, err.kind());
+        assert!(out.is_empty());
+    }
+
+    // the header ends with an empty attribute name right after the last attribute
+    fn find_table(data: &[u8]) -> usize {
+        let mut at = 8;
+        while data[at] != 0 {
+            let name_end = at + data[at..].iter().position(|&b| b == 0).unwrap();
+            let kind_end = name_end + 1 + data[name_end + 1..].iter().position(|&b| b == 0).unwrap();
+            let size = read_i32(data, kind_end + 1) as usize;
+            at = kind_end + 5 + size;
+        }
+        at + 1
+    }
+}
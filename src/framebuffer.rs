@@ -0,0 +1,56 @@
+use crate::color::RGBColor;
+
+// rendered image held in memory, as linear colors stored row by row starting from the top
+#[derive(Debug,PartialEq,Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<RGBColor>,
+    // extra named channels (arbitrary output variables) such as depth or normals,
+    // stored in the same order as the pixels
+    aovs: Vec<(String, Vec<f32>)>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![RGBColor::new(0.0, 0.0, 0.0); width * height],
+            aovs: Vec::new(),
+        }
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+    pub fn get(&self, x: usize, y: usize) -> RGBColor {
+        self.pixels[y * self.width + x]
+    }
+    pub fn set(&mut self, x: usize, y: usize, color: RGBColor) {
+        self.pixels[y * self.width + x] = color;
+    }
+    pub fn pixels(&self) -> &[RGBColor] {
+        &self.pixels
+    }
+
+    // adds a channel filled with zeros, does nothing if it already exists
+    pub fn add_aov(&mut self, name: &str) {
+        if self.aov(name).is_none() {
+            self.aovs.push((name.to_string(), vec![0.0; self.width * self.height]));
+        }
+    }
+    pub fn aov(&self, name: &str) -> Option<&[f32]> {
+        self.aovs.iter().find(|(n, _)| n == name).map(|(_, data)| data.as_slice())
+    }
+    pub fn set_aov(&mut self, name: &str, x: usize, y: usize, value: f32) {
+        let width = self.width;
+        let (_, data) = self.aovs.iter_mut().find(|(n, _)| n == name).expect("Unknown output variable");
+        data[y * width + x] = value;
+    }
+    pub fn aovs(&self) -> impl Iterator<Item = (&str, &[f32])> {
+        self.aovs.iter().map(|(n, data)| (n.as_str(), data.as_slice()))
+    }
+}
@@ -0,0 +1,415 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use fastrand::Rng;
+
+use crate::{aabb::Aabb, color::RGBColor, hittable::HitRecord, material::Material, medium::PhaseFunction, ray::Ray,
+            texture::Texture, vector3::Vector3};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// scalar values on a regular 3d grid of cells, like the density of a cloud
+pub struct VoxelGrid {
+    // cells along x, y and z
+    size: [usize; 3],
+    // x changing fastest, then y, then z
+    values: Vec<f32>,
+    max: f64,
+}
+
+impl VoxelGrid {
+    pub fn new(size: [usize; 3], values: Vec<f32>) -> Self {
+        assert_eq!(size[0] * size[1] * size[2], values.len(), "Grid size doesn't match its values");
+        assert!(!values.is_empty(), "Grid is empty");
+        let max = values.iter().fold(0.0, |max: f64, v| max.max(*v as f64));
+        VoxelGrid {size, values, max}
+    }
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+    // the largest value, or 0 if all are smaller
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+    pub fn get(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.size[1] + y) * self.size[0] + x] as f64
+    }
+
+    // trilinear between the centers of the cells, for `p` in the [0, 1] cube the grid covers,
+    // and 0 outside of it
+    pub fn value(&self, p: Vector3) -> f64 {
+        if !(0.0..=1.0).contains(&p.x) || !(0.0..=1.0).contains(&p.y) || !(0.0..=1.0).contains(&p.z) {
+            return 0.0
+        }
+        let mut cells = [(0, 0, 0.0); 3];
+        for (axis, cell) in cells.iter_mut().enumerate() {
+            let x = p[axis] * self.size[axis] as f64 - 0.5;
+            let x0 = x.floor();
+            let last = self.size[axis] as i64 - 1;
+            *cell = ((x0 as i64).clamp(0, last) as usize, (x0 as i64 + 1).clamp(0, last) as usize, x - x0);
+        }
+        let [(x0, x1, fx), (y0, y1, fy), (z0, z1, fz)] = cells;
+        let lerp = |a: f64, b: f64, t: f64| a * (1.0 - t) + b * t;
+        let plane = |z: usize| lerp(
+            lerp(self.get(x0, y0, z), self.get(x1, y0, z), fx),
+            lerp(self.get(x0, y1, z), self.get(x1, y1, z), fx),
+            fy,
+        );
+        lerp(plane(z0), plane(z1), fz)
+    }
+
+    // number of cells of a grid size read from a file, which mustn't be empty or overflow
+    // when counting its bytes
+    fn cell_count(size: [usize; 3]) -> io::Result<usize> {
+        let count = size.iter().try_fold(1usize, |count, n| count.checked_mul(*n))
+            .filter(|count| count.checked_mul(8).is_some())
+            .ok_or_else(|| invalid(format!("grid of size {:?} is too large", size)))?;
+        match count {
+            0 => Err(invalid(format!("grid of size {:?} is empty", size))),
+            _ => Ok(count),
+        }
+    }
+
+    // little endian 32-bit floats without a header, x changing fastest
+    pub fn read_raw(reader: &mut dyn Read, size: [usize; 3]) -> io::Result<Self> {
+        let mut bytes = vec![0; VoxelGrid::cell_count(size)? * 4];
+        reader.read_exact(&mut bytes)?;
+        let values = bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        Ok(VoxelGrid::new(size, values))
+    }
+
+    // 3d numpy array of shape (depth, height, width), of little endian 32 or 64-bit floats in
+    // c order, as written by `numpy.save`
+    pub fn read_npy(reader: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic[..6] != b"\x93NUMPY" {
+            return Err(invalid("not a numpy file".to_string()))
+        }
+        // the header length has 2 bytes in version 1, 4 after
+        let header_len = match magic[6] {
+            1 => {
+                let mut len = [0; 2];
+                reader.read_exact(&mut len)?;
+                u16::from_le_bytes(len) as usize
+            }
+            _ => {
+                let mut len = [0; 4];
+                reader.read_exact(&mut len)?;
+                u32::from_le_bytes(len) as usize
+            }
+        };
+        let mut header = vec![0; header_len];
+        reader.read_exact(&mut header)?;
+        let header = String::from_utf8_lossy(&header);
+
+        // a python dict literal like {'descr': '<f4', 'fortran_order': False, 'shape': (2, 3, 4), }
+        let field = |key: &str| header.split_once(&format!("'{}':", key)).map(|(_, rest)| rest.trim_start().to_string());
+        let descr = field("descr").and_then(|d| d.split('\'').nth(1).map(String::from))
+            .ok_or_else(|| invalid("numpy header without a type".to_string()))?;
+        if field("fortran_order").is_some_and(|f| f.starts_with("True")) {
+            return Err(invalid("numpy arrays in fortran order are not supported".to_string()))
+        }
+        let shape: Vec<usize> = field("shape")
+            .and_then(|s| s.strip_prefix('(').and_then(|s| s.split_once(')')).map(|(dims, _)| dims.to_string()))
+            .map(|dims| dims.split(',').map(str::trim).filter(|d| !d.is_empty()).filter_map(|d| d.parse().ok()).collect())
+            .unwrap_or_default();
+        let size = match shape[..] {
+            [depth, height, width] => [width, height, depth],
+            _ => return Err(invalid(format!("expected a 3d numpy array, found shape {:?}", shape))),
+        };
+
+        let count = VoxelGrid::cell_count(size)?;
+        let values = match descr.as_str() {
+            "<f4" => return VoxelGrid::read_raw(reader, size),
+            "<f8" => {
+                let mut bytes = vec![0; count * 8];
+                reader.read_exact(&mut bytes)?;
+                bytes.chunks_exact(8).map(|b| f64::from_le_bytes(b.try_into().unwrap()) as f32).collect()
+            }
+            _ => return Err(invalid(format!("unsupported numpy type {}, expected <f4 or <f8", descr))),
+        };
+        Ok(VoxelGrid::new(size, values))
+    }
+
+    // `.npy` files, or `.raw` ones with the given size
+    pub fn load(path: &Path, size: Option<[usize; 3]>) -> io::Result<Self> {
+        let extension = path.extension().and_then(|e| e.to_str()).map(|e| e.to_lowercase());
+        let reader = || File::open(path).map(BufReader::new);
+        match (extension.as_deref(), size) {
+            (Some("npy"), _) => VoxelGrid::read_npy(&mut reader()?),
+            (Some("raw"), Some(size)) => VoxelGrid::read_raw(&mut reader()?, size),
+            (Some("raw"), None) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("size of {} is unknown", path.display()))),
+            _ => Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported grid format {}", path.display()))),
+        }
+    }
+}
+
+// light given off inside a volume, per unit length
+pub enum Emission {
+    None,
+    // the color where the density grid is 1, in proportion to the density
+    Density(RGBColor),
+    // black body light of a grid of temperatures, with `kelvin` degrees per unit of the grid.
+    // Brightness grows with the fourth power of temperature, being `strength` at 1000 K
+    Blackbody {temperature: VoxelGrid, kelvin: f64, strength: f64},
+}
+
+// blackbody colors precomputed over the temperatures of a grid
+const PALETTE_SIZE: usize = 256;
+
+// participating medium with a density varying over a grid stretched over a box, like a cloud
+pub struct GridVolume {
+    density: VoxelGrid,
+    bounds: Aabb,
+    // interactions per unit length where the grid is 1
+    scale: f64,
+    // `Material::Medium`, scattering at interactions
+    material: Material,
+    emission: Emission,
+    // blackbody colors from 0 to the hottest temperature
+    palette: Vec<RGBColor>,
+}
+
+impl GridVolume {
+    pub fn new(density: VoxelGrid, bounds: Aabb, scale: f64, albedo: Texture, phase: PhaseFunction, emission: Emission) -> Self {
+        let palette = match &emission {
+            Emission::Blackbody {temperature, kelvin, ..} => (0..PALETTE_SIZE)
+                .map(|i| RGBColor::blackbody(f64::max(100.0, temperature.max() * kelvin * i as f64 / (PALETTE_SIZE - 1) as f64)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        GridVolume {density, bounds, scale, material: Material::Medium(albedo, phase), emission, palette}
+    }
+    pub fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+
+    // position of a world point in the [0, 1] cube of the grids
+    fn local(&self, p: Vector3) -> Vector3 {
+        let extent = self.bounds.extent();
+        let d = p - self.bounds.min;
+        Vector3::new(d.x / extent.x, d.y / extent.y, d.z / extent.z)
+    }
+    // interactions per unit length
+    pub fn density(&self, p: Vector3) -> f64 {
+        self.scale * self.density.value(self.local(p))
+    }
+    // bound of the density over the whole volume
+    pub fn majorant(&self) -> f64 {
+        self.scale * self.density.max()
+    }
+    pub fn emission(&self, p: Vector3) -> RGBColor {
+        match &self.emission {
+            Emission::None => RGBColor::new(0.0, 0.0, 0.0),
+            Emission::Density(color) => *color * self.density.value(self.local(p)),
+            Emission::Blackbody {temperature, kelvin, strength} => {
+                let t = temperature.value(self.local(p)) * kelvin;
+                let hottest = temperature.max() * kelvin;
+                if t <= 0.0 || hottest <= 0.0 {
+                    return RGBColor::new(0.0, 0.0, 0.0)
+                }
+                let color = self.palette[((t / hottest) * (PALETTE_SIZE - 1) as f64).round() as usize];
+                color * (strength * (t / 1000.0).powi(4))
+            }
+        }
+    }
+
+    // range of the ray inside the box, within [t_min, t_max]
+    fn segment(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut start, mut end) = (t_min, t_max);
+        for axis in 0..3 {
+            let inv = 1.0 / ray.direction[axis];
+            let mut t0 = (self.bounds.min[axis] - ray.origin[axis]) * inv;
+            let mut t1 = (self.bounds.max[axis] - ray.origin[axis]) * inv;
+            if inv < 0.0 {
+                (t0, t1) = (t1, t0);
+            }
+            // nan for rays parallel to and in a slab's face, counted as inside
+            start = if t0 > start { t0 } else { start };
+            end = if t1 < end { t1 } else { end };
+        }
+        match start < end {
+            true => Some((start, end)),
+            false => None,
+        }
+    }
+}
+
+// what happened to a ray going through the volumes
+pub struct VolumeEvent<'a> {
+    // where it scattered, if it did before t_max
+    pub collision: Option<HitRecord<'a>>,
+    // light emitted by the volumes along the way, reaching the ray's origin
+    pub emitted: RGBColor,
+}
+
+// volumes the ray goes through between t_min and t_max, with their combined majorant and the
+// range covered by any of them
+fn overlapping<'a>(volumes: &'a [GridVolume], ray: &Ray, t_min: f64, t_max: f64) -> (Vec<&'a GridVolume>, f64, f64, f64) {
+    let (mut start, mut end, mut majorant) = (f64::INFINITY, f64::NEG_INFINITY, 0.0);
+    let mut crossed = Vec::new();
+    for volume in volumes {
+        if volume.majorant() <= 0.0 && matches!(volume.emission, Emission::None) {
+            continue
+        }
+        if let Some((a, b)) = volume.segment(ray, t_min, t_max) {
+            start = start.min(a);
+            end = end.max(b);
+            majorant += volume.majorant();
+            crossed.push(volume);
+        }
+    }
+    (crossed, majorant, start, end)
+}
+
+// delta tracking: steps of random length with the combined majorant of the volumes, each one a
+// collision with the probability that the real density makes up of the majorant. Emission is
+// gathered at every step, which makes up for the steps being taken where the medium is thin
+pub fn delta_track<'a>(volumes: &'a [GridVolume], ray: &Ray, t_min: f64, t_max: f64, rng: &Rng) -> VolumeEvent<'a> {
+    let mut event = VolumeEvent {collision: None, emitted: RGBColor::new(0.0, 0.0, 0.0)};
+    let (crossed, majorant, start, end) = overlapping(volumes, ray, t_min, t_max);
+    if crossed.is_empty() {
+        return event
+    }
+    if majorant <= 0.0 {
+        // only emission, gathered in one uniformly placed step
+        let t = start + rng.f64() * (end - start);
+        let length = (end - start) * ray.direction.length();
+        for volume in &crossed {
+            event.emitted = event.emitted + volume.emission(ray.at(t)) * length;
+        }
+        return event
+    }
+
+    let speed = ray.direction.length();
+    let mut t = start;
+    loop {
+        t -= f64::ln(1.0 - rng.f64()) / (majorant * speed);
+        if t >= end {
+            return event
+        }
+        let point = ray.at(t);
+        let mut densities = Vec::with_capacity(crossed.len());
+        for volume in &crossed {
+            event.emitted = event.emitted + volume.emission(point) * (1.0 / majorant);
+            densities.push(volume.density(point));
+        }
+
+        // a real collision, with the volume picked in proportion to its density
+        let mut pick = rng.f64() * majorant;
+        for (volume, density) in crossed.iter().zip(densities) {
+            if pick < density {
+                let mut rec = HitRecord::new();
+                rec.t = t;
+                rec.point = point;
+                rec.normal = -ray.direction.unit();
+                rec.front_face = true;
+                rec.material = &volume.material;
+                event.collision = Some(rec);
+                return event
+            }
+            pick -= density;
+        }
+    }
+}
+
+// ratio tracking: the fraction of light getting through the volumes between t_min and t_max,
+// estimated with the same steps as delta tracking, each one keeping the fraction of the
+// majorant that isn't real density
+pub fn ratio_track(volumes: &[GridVolume], ray: &Ray, t_min: f64, t_max: f64, rng: &Rng) -> f64 {
+    let (crossed, majorant, start, end) = overlapping(volumes, ray, t_min, t_max);
+    if crossed.is_empty() || majorant <= 0.0 {
+        return 1.0
+    }
+    let speed = ray.direction.length();
+    let mut transmittance = 1.0;
+    let mut t = start;
+    loop {
+        t -= f64::ln(1.0 - rng.f64()) / (majorant * speed);
+        if t >= end {
+            return transmittance
+        }
+        let point = ray.at(t);
+        let density: f64 = crossed.iter().map(|volume| volume.density(point)).sum();
+        transmittance *= 1.0 - f64::min(1.0, density / majorant);
+        // too dark to matter, ended at random to stay unbiased
+        if transmittance < 0.1 {
+            if rng.f64() < 0.5 {
+                return 0.0
+            }
+            transmittance *= 2.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{delta_track, ratio_track, Emission, GridVolume, VoxelGrid};
+    use crate::{aabb::Aabb, color::RGBColor, medium::PhaseFunction, ray::Ray, vector3::Vector3};
+
+    fn npy(shape: &str, values: &[f32]) -> Vec<u8> {
+        let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': {}, }}", shape);
+        while (header.len() + 11) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(values.iter().flat_map(|v| v.to_le_bytes()));
+        bytes
+    }
+
+    #[test]
+    fn grid_test() {
+        // 2 wide, 1 high and 1 deep
+        let grid = VoxelGrid::read_npy(&mut npy("(1, 1, 2)", &[0.0, 1.0]).as_slice()).unwrap();
+        assert_eq!([2, 1, 1], grid.size());
+        assert_eq!(1.0, grid.max());
+        // at the cell centers, halfway between them, and clamped to the edge ones
+        assert_eq!(0.0, grid.value(Vector3::new(0.25, 0.5, 0.5)));
+        assert_eq!(0.5, grid.value(Vector3::new(0.5, 0.5, 0.5)));
+        assert_eq!(1.0, grid.value(Vector3::new(0.9, 0.1, 0.5)));
+        assert_eq!(0.0, grid.value(Vector3::new(1.1, 0.5, 0.5)));
+
+        assert!(VoxelGrid::read_npy(&mut npy("(4, 1)", &[0.0; 4]).as_slice()).is_err());
+        let empty = VoxelGrid::read_npy(&mut npy("(0, 1, 2)", &[]).as_slice()).err().unwrap();
+        assert_eq!("grid of size [2, 1, 0] is empty", empty.to_string());
+        let huge = VoxelGrid::read_npy(&mut npy("(4294967296, 4294967296, 2)", &[]).as_slice()).err().unwrap();
+        assert_eq!("grid of size [2, 4294967296, 4294967296] is too large", huge.to_string());
+        let raw: Vec<u8> = [1.0f32, 2.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+        assert_eq!(2.0, VoxelGrid::read_raw(&mut raw.as_slice(), [1, 1, 2]).unwrap().get(0, 0, 1));
+    }
+    #[test]
+    fn tracking_test() {
+        let rng = fastrand::Rng::with_seed(2);
+        // density 0.5 on the left half of a 2 unit box and 0 on the right, with the grid scaled by 2
+        let grid = VoxelGrid::new([4, 1, 1], vec![0.5, 0.5, 0.0, 0.0]);
+        let bounds = Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        let volume = GridVolume::new(grid, bounds, 2.0, RGBColor::new(1.0, 1.0, 1.0).into(), PhaseFunction::Isotropic,
+                                     Emission::Density(RGBColor::new(1.0, 1.0, 1.0)));
+        let volumes = [volume];
+
+        // through the dense half: 1 interaction per unit over 2 units
+        let ray = Ray::new(Vector3::new(-0.75, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        let n = 20000;
+        let expected = f64::exp(-2.0);
+        let ratio = (0..n).map(|_| ratio_track(&volumes, &ray, 0.001, f64::INFINITY, &rng)).sum::<f64>() / n as f64;
+        assert!((ratio - expected).abs() < 0.01, "{}", ratio);
+        let passed = (0..n).filter(|_| delta_track(&volumes, &ray, 0.001, f64::INFINITY, &rng).collision.is_none()).count();
+        assert!((passed as f64 / n as f64 - expected).abs() < 0.015);
+
+        // emission of 0.5 per unit, seen through the medium: the integral of 0.5 exp(-t) over 2 units
+        let emitted = (0..n).map(|_| delta_track(&volumes, &ray, 0.001, f64::INFINITY, &rng).emitted.r()).sum::<f64>() / n as f64;
+        assert!((emitted - 0.5 * (1.0 - f64::exp(-2.0))).abs() < 0.015, "{}", emitted);
+
+        // nothing on the empty side, and a surface in front stops the tracking
+        let empty = Ray::new(Vector3::new(0.75, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(1.0, ratio_track(&volumes, &empty, 0.001, f64::INFINITY, &rng));
+        assert_eq!(1.0, ratio_track(&volumes, &ray, 0.001, 3.0, &rng));
+    }
+}
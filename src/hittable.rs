@@ -1,29 +1,55 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::background::Background;
+use crate::bvh::Bvh;
 use crate::color::RGBColor;
+use crate::grid::GridVolume;
+use crate::instance::Instance;
 use crate::material::Material;
+use crate::medium::{Medium, Volume};
+use crate::mesh::TriangleMesh;
+use crate::texture::Texture;
+use crate::utils::{clamp, orthonormal_basis};
 
 use crate::vector3::Vector3;
 use crate::ray::Ray;
 
-pub struct HitRecord {
+// material of records that haven't been given one yet
+static DEFAULT_MATERIAL: Material = Material::Lambertian(Texture::Solid(RGBColor::new(0.0, 0.0, 0.0)));
+
+pub struct HitRecord<'a> {
     pub point: Vector3,
     pub normal: Vector3,
-    pub material: Material,
+    pub material: &'a Material,
     pub t: f64,
     pub front_face: bool,
+    // surface coordinates of the hit point, mostly in [0, 1]
+    pub u: f64,
+    pub v: f64,
 }
 
-impl HitRecord {
+impl Default for HitRecord<'_> {
+    fn default() -> Self {
+        HitRecord::new()
+    }
+}
+
+impl HitRecord<'_> {
     pub fn new() -> Self {
         // returns a default hitrecord, to be changed
         HitRecord {
             point: Vector3::new(0.0,0.0,0.0),
             normal: Vector3::new(1.0,1.0,1.0),
-            material: Material::Lambertian(RGBColor::new(0.0, 0.0, 0.0)),
+            material: &DEFAULT_MATERIAL,
             t: 0.0,
             front_face: false,
+            u: 0.0,
+            v: 0.0,
         }
     }
-    fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vector3) {
+    pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: Vector3) {
         // if its negative, the normal is in opposite direction to the ray
         self.front_face = ray.direction.dot(outward_normal) < 0.0;
         self.normal = match self.front_face {
@@ -34,16 +60,75 @@ impl HitRecord {
 }
 
 pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
 }
 
 
+// shapes closer than this to being parallel to the ray are considered missed
+const PARALLEL_EPSILON: f64 = 1e-12;
+// thickness given to the bounding boxes of flat shapes
+const FLAT_PADDING: f64 = 1e-4;
+
 pub enum Shape {
     Sphere {radius: f64, center: Vector3, material: Material},
+    // infinite plane through `point`
+    Plane {point: Vector3, normal: Vector3, material: Material},
+    // parallelogram with a corner at `corner` and sides `edge_u` and `edge_v`
+    Quad {corner: Vector3, edge_u: Vector3, edge_v: Vector3, material: Material},
+    Disk {center: Vector3, normal: Vector3, radius: f64, material: Material},
+    // the outward normal follows the counter-clockwise order of the vertices
+    Triangle {a: Vector3, b: Vector3, c: Vector3, material: Material},
+    // shared so the same mesh can be placed in several worlds without copying its buffers
+    Mesh(Arc<TriangleMesh>),
+    // another shape moved, rotated or scaled, see `Instance`
+    Instance(Arc<Instance>),
+    // participating medium filling another shape
+    Volume(Arc<Volume>),
+}
+
+// distance along the ray to the plane through `point`, if it is within the range
+fn hit_plane(ray: &Ray, point: Vector3, normal: Vector3, t_min: f64, t_max: f64) -> Option<f64> {
+    let denom = normal.dot(ray.direction);
+    if denom.abs() < PARALLEL_EPSILON {
+        return None
+    }
+    let t = (point - ray.origin).dot(normal) / denom;
+    if t < t_min || t_max < t {
+        return None
+    }
+    Some(t)
+}
+
+// Möller-Trumbore intersection, returns the distance and the barycentric coordinates of b and c
+pub fn hit_triangle(ray: &Ray, a: Vector3, b: Vector3, c: Vector3, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let edge1 = b - a;
+    let edge2 = c - a;
+    let p = ray.direction.cross(edge2);
+    let det = edge1.dot(p);
+    if det.abs() < PARALLEL_EPSILON {
+        return None
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - a;
+    let u = s.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None
+    }
+    let q = s.cross(edge1);
+    let v = ray.direction.dot(q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None
+    }
+    let t = edge2.dot(q) * inv_det;
+    if t < t_min || t_max < t {
+        return None
+    }
+    Some((t, u, v))
 }
 
 impl Hittable for Shape {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         match self {
             Shape::Sphere {radius, center, material} => {
                 let oc = ray.origin - *center;
@@ -72,58 +157,344 @@ impl Hittable for Shape {
                 hit_rec.point = ray.at(hit_rec.t);
                 let outward_normal = (hit_rec.point - *center) / *radius;
                 hit_rec.set_face_normal(ray,outward_normal);
-                hit_rec.material = *material;
-                
-                return Some(hit_rec)
+                hit_rec.material = material;
+
+                // longitude around the y axis starting from -x, and latitude from -y
+                hit_rec.u = (f64::atan2(-outward_normal.z, outward_normal.x) + PI) / (2.0 * PI);
+                hit_rec.v = f64::acos(clamp(-outward_normal.y, -1.0, 1.0)) / PI;
+
+                Some(hit_rec)
             }
+            Shape::Plane {point, normal, material} => {
+                let unit_normal = normal.unit();
+                let t = hit_plane(ray, *point, unit_normal, t_min, t_max)?;
+
+                let mut hit_rec = HitRecord::new();
+                hit_rec.t = t;
+                hit_rec.point = ray.at(t);
+                hit_rec.set_face_normal(ray, unit_normal);
+                hit_rec.material = material;
+
+                // planar mapping in world units, textures take care of repeating it
+                let (tangent, bitangent) = orthonormal_basis(unit_normal);
+                hit_rec.u = (hit_rec.point - *point).dot(tangent);
+                hit_rec.v = (hit_rec.point - *point).dot(bitangent);
+
+                Some(hit_rec)
+            }
+            Shape::Quad {corner, edge_u, edge_v, material} => {
+                let n = edge_u.cross(*edge_v);
+                let unit_normal = n.unit();
+                let t = hit_plane(ray, *corner, unit_normal, t_min, t_max)?;
+
+                // coordinates of the hit point along the two sides
+                let point = ray.at(t);
+                let planar = point - *corner;
+                let w = n / n.length_squared();
+                let alpha = w.dot(planar.cross(*edge_v));
+                let beta = w.dot(edge_u.cross(planar));
+                if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+                    return None
+                }
+
+                let mut hit_rec = HitRecord::new();
+                hit_rec.t = t;
+                hit_rec.point = point;
+                hit_rec.set_face_normal(ray, unit_normal);
+                hit_rec.material = material;
+                hit_rec.u = alpha;
+                hit_rec.v = beta;
+
+                Some(hit_rec)
+            }
+            Shape::Disk {center, normal, radius, material} => {
+                let unit_normal = normal.unit();
+                let t = hit_plane(ray, *center, unit_normal, t_min, t_max)?;
+
+                let point = ray.at(t);
+                let offset = point - *center;
+                if offset.length_squared() > radius*radius {
+                    return None
+                }
+
+                let mut hit_rec = HitRecord::new();
+                hit_rec.t = t;
+                hit_rec.point = point;
+                hit_rec.set_face_normal(ray, unit_normal);
+                hit_rec.material = material;
+
+                // angle around the center and distance from it
+                let (tangent, bitangent) = orthonormal_basis(unit_normal);
+                hit_rec.u = (f64::atan2(offset.dot(bitangent), offset.dot(tangent)) + PI) / (2.0 * PI);
+                hit_rec.v = offset.length() / radius;
+
+                Some(hit_rec)
+            }
+            Shape::Triangle {a, b, c, material} => {
+                let (t, u, v) = hit_triangle(ray, *a, *b, *c, t_min, t_max)?;
+
+                let mut hit_rec = HitRecord::new();
+                hit_rec.t = t;
+                hit_rec.point = ray.at(t);
+                hit_rec.set_face_normal(ray, (*b - *a).cross(*c - *a).unit());
+                hit_rec.material = material;
+                // barycentric coordinates of b and c
+                hit_rec.u = u;
+                hit_rec.v = v;
+
+                Some(hit_rec)
+            }
+            Shape::Mesh(mesh) => mesh.hit(ray, t_min, t_max),
+            Shape::Instance(instance) => instance.hit(ray, t_min, t_max),
+            Shape::Volume(volume) => volume.hit(ray, t_min, t_max),
         }
     }
 
 }
 
+impl Shape {
+    // infinite for planes, which the world keeps out of its bvh
+    pub fn bounding_box(&self) -> Aabb {
+        match self {
+            Shape::Sphere {radius, center, ..} => {
+                let r = Vector3::new(radius.abs(), radius.abs(), radius.abs());
+                Aabb::new(*center - r, *center + r)
+            }
+            Shape::Plane {..} => Aabb::new(
+                Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+                Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            ),
+            Shape::Quad {corner, edge_u, edge_v, ..} => {
+                Aabb::from_points(*corner, *corner + *edge_u + *edge_v)
+                    .grow(*corner + *edge_u)
+                    .grow(*corner + *edge_v)
+                    .pad(FLAT_PADDING)
+            }
+            Shape::Disk {center, normal, radius, ..} => {
+                // the extent along an axis shrinks as the disk faces that axis
+                let n = normal.unit();
+                let e = Vector3::new(
+                    radius * f64::sqrt(f64::max(0.0, 1.0 - n.x*n.x)),
+                    radius * f64::sqrt(f64::max(0.0, 1.0 - n.y*n.y)),
+                    radius * f64::sqrt(f64::max(0.0, 1.0 - n.z*n.z)),
+                );
+                Aabb::new(*center - e, *center + e).pad(FLAT_PADDING)
+            }
+            Shape::Triangle {a, b, c, ..} => Aabb::from_points(*a, *b).grow(*c).pad(FLAT_PADDING),
+            Shape::Mesh(mesh) => mesh.bounding_box(),
+            Shape::Instance(instance) => instance.bounding_box(),
+            Shape::Volume(volume) => volume.bounding_box(),
+        }
+    }
+}
+
 pub struct World {
     list: Vec<Shape>,
+    // seen by the rays that leave the scene
+    pub background: Background,
+    // medium filling the space between the shapes
+    pub fog: Option<Medium>,
+    // how far the background is from rays leaving the scene, through the fog. When infinite,
+    // the fog hides it
+    pub fog_distance: f64,
+    // acceleration structure over the bounded shapes of `list`, rebuilt by `build_bvh`
+    // and dropped whenever the list changes
+    bvh: Option<Bvh>,
+    // indices in `list` of the shapes in the bvh, and of the ones with infinite bounds
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+    // indices in `list` of the shapes sampled directly for next event estimation
+    lights: Vec<usize>,
+    // media of varying density, which rays are tracked through rather than hit
+    volumes: Vec<GridVolume>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        World::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
-        World { list: Vec::new() }
+        World { list: Vec::new(), background: Background::default(), fog: None, fog_distance: f64::INFINITY, bvh: None, bounded: Vec::new(), unbounded: Vec::new(), lights: Vec::new(), volumes: Vec::new() }
     }
     pub fn add(&mut self, elem: Shape) {
+        if elem.is_light() {
+            self.lights.push(self.list.len());
+        }
         self.list.push(elem);
+        self.bvh = None;
     }
     pub fn clear(&mut self) {
         self.list.clear();
+        self.lights.clear();
+        self.volumes.clear();
+        self.bvh = None;
     }
-}
-impl Hittable for World {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>{
-        // initialized with some default values
-        let mut hit_rec = HitRecord::new();
-        let mut hit_anything = false;
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+    pub fn add_volume(&mut self, volume: GridVolume) {
+        self.volumes.push(volume);
+    }
+    pub fn volumes(&self) -> &[GridVolume] {
+        &self.volumes
+    }
+    pub fn light_count(&self) -> usize {
+        self.lights.len()
+    }
+    pub fn light(&self, i: usize) -> &Shape {
+        &self.list[self.lights[i]]
+    }
+    // density of picking the direction by choosing one light uniformly and sampling it,
+    // counting only lights closer than `t_max`
+    pub fn light_pdf(&self, origin: Vector3, direction: Vector3, t_max: f64) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0
+        }
+        let sum: f64 = self.lights.iter().map(|&i| self.list[i].light_pdf(origin, direction, t_max)).sum();
+        sum / self.lights.len() as f64
+    }
+    pub fn build_bvh(&mut self) {
+        let mut bounds = Vec::new();
+        self.bounded.clear();
+        self.unbounded.clear();
+        for (i, shape) in self.list.iter().enumerate() {
+            let b = shape.bounding_box();
+            if b.is_finite() {
+                self.bounded.push(i);
+                bounds.push(b);
+            } else {
+                self.unbounded.push(i);
+            }
+        }
+        self.bvh = Some(Bvh::new(&bounds));
+    }
+    pub fn hit_linear(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest: Option<HitRecord<'_>> = None;
         let mut closest_so_far = t_max;
 
         for shape in &self.list {
-            match shape.hit(ray, t_min, closest_so_far) {
-                Some(curr_rec) => {
-                    hit_anything = true;
-                    closest_so_far = curr_rec.t;
-
-                    hit_rec.point = curr_rec.point;
-                    hit_rec.normal = curr_rec.normal;
-                    hit_rec.material = curr_rec.material;
-                    hit_rec.t = curr_rec.t;
-                    hit_rec.front_face = curr_rec.front_face;
-                },
-                None => continue,
+            if let Some(curr_rec) = shape.hit(ray, t_min, closest_so_far) {
+                closest_so_far = curr_rec.t;
+                closest = Some(curr_rec);
             }
         }
 
-        if hit_anything {
-            Some(hit_rec)
-        } else {
-            None
+        closest
+    }
+}
+impl Hittable for World {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let bvh = match &self.bvh {
+            Some(bvh) => bvh,
+            None => return self.hit_linear(ray, t_min, t_max),
+        };
+
+        // `bounded` is sorted, so the bvh breaks ties in the same order as the list
+        let mut closest = bvh.hit(ray, t_min, t_max, |i, closest_so_far| self.list[self.bounded[i]].hit(ray, t_min, closest_so_far))
+            .map(|(rec, i)| (rec, self.bounded[i]));
+        for &i in &self.unbounded {
+            let closest_so_far = closest.as_ref().map_or(t_max, |(rec, _)| rec.t);
+            if let Some(rec) = self.list[i].hit(ray, t_min, closest_so_far) {
+                let replaces = match &closest {
+                    Some((best, best_index)) => rec.t < best.t || (rec.t == best.t && i > *best_index),
+                    None => true,
+                };
+                if replaces {
+                    closest = Some((rec, i));
+                }
+            }
         }
+
+        closest.map(|(rec, _)| rec)
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::{Hittable, Shape, World};
+    use crate::{color::RGBColor, material::Material, ray::Ray, utils::{random_scene, random_vec_in_unit_sphere}, vector3::Vector3};
+
+    fn gray() -> Material {
+        Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into())
+    }
+    fn down_ray(x: f64, z: f64) -> Ray {
+        Ray::new(Vector3::new(x, 5.0, z), Vector3::new(0.0, -1.0, 0.0))
+    }
+
+    #[test]
+    fn plane_test() {
+        let plane = Shape::Plane { point: Vector3::new(0.0, 1.0, 0.0), normal: Vector3::new(0.0, 2.0, 0.0), material: gray() };
+        let rec = plane.hit(&down_ray(3.0, -2.0), 0.001, f64::INFINITY).unwrap();
+
+        assert_eq!((4.0, Vector3::new(0.0, 1.0, 0.0), true), (rec.t, rec.normal, rec.front_face));
+        assert!(plane.hit(&Ray::new(Vector3::new(0.0, 5.0, 0.0), Vector3::new(1.0, 0.0, 0.0)), 0.001, f64::INFINITY).is_none());
+    }
+    #[test]
+    fn quad_test() {
+        let quad = Shape::Quad {
+            corner: Vector3::new(0.0, 0.0, 0.0),
+            edge_u: Vector3::new(2.0, 0.0, 0.0),
+            edge_v: Vector3::new(0.0, 0.0, 4.0),
+            material: gray(),
+        };
+        let rec = quad.hit(&down_ray(0.5, 3.0), 0.001, f64::INFINITY).unwrap();
+
+        // the outward normal edge_u x edge_v points down, so the ray hits the back face
+        assert_eq!((5.0, 0.25, 0.75, false), (rec.t, rec.u, rec.v, rec.front_face));
+        assert_eq!(Vector3::new(0.0, 1.0, 0.0), rec.normal);
+        assert!(quad.hit(&down_ray(2.5, 3.0), 0.001, f64::INFINITY).is_none());
+    }
+    #[test]
+    fn disk_test() {
+        let disk = Shape::Disk { center: Vector3::new(1.0, 0.0, 1.0), normal: Vector3::new(0.0, 1.0, 0.0), radius: 1.0, material: gray() };
+        let rec = disk.hit(&down_ray(1.5, 1.0), 0.001, f64::INFINITY).unwrap();
+
+        assert_eq!((5.0, 0.5), (rec.t, rec.v));
+        assert!(disk.hit(&down_ray(1.8, 1.8), 0.001, f64::INFINITY).is_none());
+    }
+    #[test]
+    fn triangle_test() {
+        let triangle = Shape::Triangle {
+            a: Vector3::new(0.0, 0.0, 0.0),
+            b: Vector3::new(0.0, 0.0, 1.0),
+            c: Vector3::new(1.0, 0.0, 0.0),
+            material: gray(),
+        };
+        let rec = triangle.hit(&down_ray(0.25, 0.5), 0.001, f64::INFINITY).unwrap();
+
+        assert_eq!((5.0, 0.5, 0.25, true), (rec.t, rec.u, rec.v, rec.front_face));
+        assert!(triangle.hit(&down_ray(0.6, 0.6), 0.001, f64::INFINITY).is_none());
+    }
+    #[test]
+    fn unbounded_bvh_test() {
+        let mut world = World::new();
+        world.add(Shape::Plane { point: Vector3::new(0.0, 0.0, 0.0), normal: Vector3::new(0.0, 1.0, 0.0), material: gray() });
+        world.add(Shape::Sphere { center: Vector3::new(0.0, 1.0, 0.0), radius: 0.5, material: gray() });
+        world.build_bvh();
+
+        assert_eq!(3.5, world.hit(&down_ray(0.0, 0.0), 0.001, f64::INFINITY).unwrap().t);
+        assert_eq!(5.0, world.hit(&down_ray(2.0, 0.0), 0.001, f64::INFINITY).unwrap().t);
+    }
+
+    #[test]
+    fn bvh_matches_linear_test() {
+        let rng = fastrand::Rng::with_seed(3);
+        let mut world = random_scene(&rng);
+        world.build_bvh();
+
+        for _ in 0..2000 {
+            let origin = Vector3::new(rng.f64()*30.0 - 15.0, rng.f64()*6.0, rng.f64()*30.0 - 15.0);
+            let ray = Ray::new(origin, random_vec_in_unit_sphere(&rng));
+
+            let linear = world.hit_linear(&ray, 0.001, f64::INFINITY).map(|rec| (rec.t, rec.point, rec.normal));
+            let bvh = world.hit(&ray, 0.001, f64::INFINITY).map(|rec| (rec.t, rec.point, rec.normal));
+            assert_eq!(linear, bvh);
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use crate::{color::RGBColor, framebuffer::Framebuffer};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn from_rgbe(rgbe: [u8; 4]) -> RGBColor {
+    if rgbe[3] == 0 {
+        return RGBColor::new(0.0, 0.0, 0.0)
+    }
+    let f = 2f64.powi(rgbe[3] as i32 - (128 + 8));
+    RGBColor::new(rgbe[0] as f64 * f, rgbe[1] as f64 * f, rgbe[2] as f64 * f)
+}
+
+// reads one component of a run length encoded scanline
+fn read_hdr_rle(reader: &mut dyn Read, out: &mut [u8]) -> io::Result<()> {
+    let mut x = 0;
+    while x < out.len() {
+        let mut count = [0u8; 1];
+        reader.read_exact(&mut count)?;
+        let (run, count) = match count[0] > 128 {
+            true => (true, count[0] as usize - 128),
+            false => (false, count[0] as usize),
+        };
+        if count == 0 || x + count > out.len() {
+            return Err(invalid("bad run length in hdr scanline".to_string()))
+        }
+        if run {
+            let mut value = [0u8; 1];
+            reader.read_exact(&mut value)?;
+            out[x..x + count].fill(value[0]);
+        } else {
+            reader.read_exact(&mut out[x..x + count])?;
+        }
+        x += count;
+    }
+    Ok(())
+}
+
+// Radiance RGBE image, with the usual top to bottom, left to right orientation
+pub fn read_hdr(reader: &mut dyn BufRead) -> io::Result<Framebuffer> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a radiance hdr file".to_string()))
+    }
+    // header variables up to an empty line
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("hdr header ends unexpectedly".to_string()))
+        }
+        let l = line.trim();
+        if l.is_empty() {
+            break;
+        }
+        if let Some(format) = l.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid(format!("unsupported hdr pixel format {}", format)))
+            }
+        }
+    }
+    line.clear();
+    reader.read_line(&mut line)?;
+    let resolution: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match resolution.as_slice() {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| invalid(format!("bad hdr height {}", h)))?,
+            w.parse::<usize>().map_err(|_| invalid(format!("bad hdr width {}", w)))?,
+        ),
+        _ => return Err(invalid(format!("unsupported hdr orientation {}", line.trim()))),
+    };
+    if width == 0 || height == 0 {
+        return Err(invalid("empty hdr image".to_string()))
+    }
+
+    let mut image = Framebuffer::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+    let mut component = vec![0u8; width];
+    for y in 0..height {
+        let mut start = [0u8; 4];
+        reader.read_exact(&mut start)?;
+        let rle = (8..0x8000).contains(&width) && start[0] == 2 && start[1] == 2 && start[2] & 0x80 == 0;
+        if rle {
+            if ((start[2] as usize) << 8 | start[3] as usize) != width {
+                return Err(invalid("hdr scanline width mismatch".to_string()))
+            }
+            for i in 0..4 {
+                read_hdr_rle(reader, &mut component)?;
+                for (x, p) in scanline.iter_mut().enumerate() {
+                    p[i] = component[x];
+                }
+            }
+        } else {
+            // flat scanline, the first pixel is already read
+            scanline[0] = start;
+            for p in scanline.iter_mut().skip(1) {
+                reader.read_exact(p)?;
+            }
+        }
+        for (x, p) in scanline.iter().enumerate() {
+            image.set(x, y, from_rgbe(*p));
+        }
+    }
+    Ok(image)
+}
+
+pub fn load_hdr(path: &Path) -> io::Result<Framebuffer> {
+    read_hdr(&mut BufReader::new(File::open(path)?))
+}
+
+// inverse of the sRGB transfer function, for 8 and 16 bit images that store display values
+pub fn srgb_to_linear(c: f64) -> f64 {
+    match c <= 0.04045 {
+        true => c / 12.92,
+        false => ((c + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+// builds an image from interleaved samples in [0, 1] with gray, gray and alpha, rgb or rgba
+// pixels. Alpha is dropped
+fn from_samples(width: usize, height: usize, channels: usize, srgb: bool, sample: impl Fn(usize) -> f64) -> Framebuffer {
+    let decode = |c: f64| match srgb {
+        true => srgb_to_linear(c),
+        false => c,
+    };
+    let mut image = Framebuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) * channels;
+            let color = match channels {
+                1 | 2 => {
+                    let l = decode(sample(i));
+                    RGBColor::new(l, l, l)
+                }
+                _ => RGBColor::new(decode(sample(i)), decode(sample(i + 1)), decode(sample(i + 2))),
+            };
+            image.set(x, y, color);
+        }
+    }
+    image
+}
+
+// any png, with palettes and low bit depths expanded to 8 bits
+pub fn read_png(reader: &mut dyn Read, srgb: bool) -> io::Result<Framebuffer> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| invalid(e.to_string()))?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(|e| invalid(e.to_string()))?;
+    let (width, height) = (info.width as usize, info.height as usize);
+    let channels = info.color_type.samples();
+
+    Ok(match info.bit_depth {
+        png::BitDepth::Sixteen => from_samples(width, height, channels, srgb,
+            |i| u16::from_be_bytes([data[2 * i], data[2 * i + 1]]) as f64 / 65535.0),
+        _ => from_samples(width, height, channels, srgb, |i| data[i] as f64 / 255.0),
+    })
+}
+
+// baseline and progressive jpegs, in grayscale or color
+pub fn read_jpeg(reader: &mut dyn Read, srgb: bool) -> io::Result<Framebuffer> {
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    let data = decoder.decode().map_err(|e| invalid(e.to_string()))?;
+    let info = decoder.info().ok_or_else(|| invalid("jpeg has no image".to_string()))?;
+    let (width, height) = (info.width as usize, info.height as usize);
+
+    match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => Ok(from_samples(width, height, 1, srgb, |i| data[i] as f64 / 255.0)),
+        jpeg_decoder::PixelFormat::L16 => Ok(from_samples(width, height, 1, srgb,
+            |i| u16::from_ne_bytes([data[2 * i], data[2 * i + 1]]) as f64 / 65535.0)),
+        jpeg_decoder::PixelFormat::RGB24 => Ok(from_samples(width, height, 3, srgb, |i| data[i] as f64 / 255.0)),
+        jpeg_decoder::PixelFormat::CMYK32 => Err(invalid("cmyk jpegs aren't supported".to_string())),
+    }
+}
+
+// png, jpeg or radiance hdr file, picked by extension. `srgb` tells whether the 8 and 16 bit
+// formats hold sRGB encoded colors, as opposed to linear data such as normal or bump maps.
+// Hdr files are always linear
+pub fn load_image(path: &Path, srgb: bool) -> io::Result<Framebuffer> {
+    let extension = path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mut reader = BufReader::new(File::open(path)?);
+    match extension.as_str() {
+        "png" => read_png(&mut reader, srgb),
+        "jpg" | "jpeg" => read_jpeg(&mut reader, srgb),
+        "hdr" => read_hdr(&mut reader),
+        _ => Err(io::Error::new(io::ErrorKind::Unsupported, format!("unsupported image format {}", path.display()))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{read_hdr, read_jpeg, read_png, srgb_to_linear};
+    use crate::{color::RGBColor, framebuffer::Framebuffer, image_writer::{HdrWriter, ImageWriter}};
+
+    #[test]
+    fn hdr_roundtrip_test() {
+        // wide enough for run length encoding, and narrow enough for flat scanlines
+        for width in [3, 20] {
+            let mut image = Framebuffer::new(width, 2);
+            image.set(1, 0, RGBColor::new(1.0, 0.5, 0.0));
+            image.set(2, 1, RGBColor::new(0.0, 8.0, 0.25));
+            let mut out = Vec::new();
+            HdrWriter.write(&image, &mut out).unwrap();
+
+            assert_eq!(image, read_hdr(&mut out.as_slice()).unwrap());
+        }
+        for resolution in ["-Y 1 +X 0", "-Y 0 +X 4"] {
+            let header = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution);
+            let err = read_hdr(&mut header.as_bytes()).unwrap_err();
+            assert_eq!("empty hdr image", err.to_string());
+        }
+    }
+    #[test]
+    fn png_test() {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, 2, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.write_header().unwrap().write_image_data(&[255, 0, 0, 255, 0, 128, 255, 0]).unwrap();
+
+        let image = read_png(&mut out.as_slice(), true).unwrap();
+        assert_eq!(RGBColor::new(1.0, 0.0, 0.0), image.get(0, 0));
+        assert_eq!(RGBColor::new(0.0, srgb_to_linear(128.0 / 255.0), 1.0), image.get(1, 0));
+        let raw = read_png(&mut out.as_slice(), false).unwrap();
+        assert_eq!(RGBColor::new(0.0, 128.0 / 255.0, 1.0), raw.get(1, 0));
+
+        // 16 bit gray
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, 1, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        encoder.write_header().unwrap().write_image_data(&[0x80, 0x00]).unwrap();
+        let l = 32768.0 / 65535.0;
+        assert_eq!(RGBColor::new(l, l, l), read_png(&mut out.as_slice(), false).unwrap().get(0, 0));
+    }
+    #[test]
+    fn jpeg_test() {
+        // flat colors survive compression almost unchanged
+        let pixels: Vec<u8> = (0..16 * 8).flat_map(|i| match i % 16 < 8 {
+            true => [200, 40, 40],
+            false => [40, 40, 200],
+        }).collect();
+        let mut out = Vec::new();
+        jpeg_encoder::Encoder::new(&mut out, 100).encode(&pixels, 16, 8, jpeg_encoder::ColorType::Rgb).unwrap();
+
+        let image = read_jpeg(&mut out.as_slice(), false).unwrap();
+        assert_eq!((16, 8), (image.width(), image.height()));
+        let (left, right) = (image.get(2, 4), image.get(13, 4));
+        assert!((left.r() - 200.0 / 255.0).abs() < 0.02 && (left.b() - 40.0 / 255.0).abs() < 0.02);
+        assert!((right.b() - 200.0 / 255.0).abs() < 0.02 && (right.r() - 40.0 / 255.0).abs() < 0.02);
+    }
+}
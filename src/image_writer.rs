@@ -0,0 +1,263 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::{exr::ExrWriter, framebuffer::Framebuffer};
+
+// encodes a framebuffer into an image format. Implement it to add new output formats
+pub trait ImageWriter {
+    fn write(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()>;
+}
+
+// binary portable pixmap (P6), gamma corrected
+pub struct PpmWriter;
+// 8-bit RGB PNG, gamma corrected
+pub struct PngWriter;
+// Radiance RGBE (.hdr), linear
+pub struct HdrWriter;
+// portable float map (.pfm), linear
+pub struct PfmWriter;
+
+impl ImageWriter for PpmWriter {
+    fn write(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", image.width(), image.height())?;
+        let data: Vec<u8> = image.pixels().iter().flat_map(|c| c.to_rgb8()).collect();
+        out.write_all(&data)
+    }
+}
+
+impl ImageWriter for PngWriter {
+    fn write(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(out, image.width() as u32, image.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let data: Vec<u8> = image.pixels().iter().flat_map(|c| c.to_rgb8()).collect();
+        let mut png_writer = encoder.write_header().map_err(io::Error::other)?;
+        png_writer.write_image_data(&data).map_err(io::Error::other)?;
+        png_writer.finish().map_err(io::Error::other)
+    }
+}
+
+// formats written row by row need at least one pixel
+pub(crate) fn check_not_empty(image: &Framebuffer) -> io::Result<()> {
+    match image.width() == 0 || image.height() == 0 {
+        true => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't write an empty {}x{} image", image.width(), image.height()))),
+        false => Ok(()),
+    }
+}
+
+// shared exponent encoding used by Radiance files
+fn to_rgbe(r: f64, g: f64, b: f64) -> [u8; 4] {
+    let v = f64::max(r, f64::max(g, b));
+    if v.is_nan() || v < 1e-32 {
+        return [0, 0, 0, 0]
+    }
+    // v = mantissa * 2^exponent, with mantissa in [0.5, 1)
+    let exponent = v.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f64.powi(exponent);
+    let encode = |c: f64| f64::min(f64::max(c, 0.0) * scale, 255.0) as u8;
+
+    [encode(r), encode(g), encode(b), (exponent + 128).clamp(0, 255) as u8]
+}
+
+// run length encoding of a single component of a scanline, as in Greg Ward's reference code
+fn write_hdr_rle(data: &[u8], out: &mut Vec<u8>) {
+    const MIN_RUN: usize = 4;
+    let mut cur = 0;
+    while cur < data.len() {
+        // look for the next run long enough to be worth encoding
+        let mut beg_run = cur;
+        let mut run_count = 0;
+        while run_count < MIN_RUN && beg_run < data.len() {
+            beg_run += run_count;
+            run_count = 1;
+            while beg_run + run_count < data.len() && run_count < 127 && data[beg_run] == data[beg_run + run_count] {
+                run_count += 1;
+            }
+        }
+        if run_count < MIN_RUN {
+            beg_run = data.len();
+        }
+        // the literal bytes before the run
+        while cur < beg_run {
+            let count = usize::min(128, beg_run - cur);
+            out.push(count as u8);
+            out.extend_from_slice(&data[cur..cur + count]);
+            cur += count;
+        }
+        if run_count >= MIN_RUN {
+            out.push(128 + run_count as u8);
+            out.push(data[beg_run]);
+            cur += run_count;
+        }
+    }
+}
+
+impl ImageWriter for HdrWriter {
+    fn write(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        check_not_empty(image)?;
+        let width = image.width();
+        write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", image.height(), width)?;
+
+        // run length encoding is only defined for these widths
+        let rle = (8..0x8000).contains(&width);
+        let mut scanline = Vec::with_capacity(4 * width + 4);
+        let mut component = vec![0u8; width];
+        for row in image.pixels().chunks(width) {
+            let rgbe: Vec<[u8; 4]> = row.iter().map(|c| to_rgbe(c.r(), c.g(), c.b())).collect();
+            scanline.clear();
+            if rle {
+                scanline.extend_from_slice(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8]);
+                for i in 0..4 {
+                    for (x, p) in rgbe.iter().enumerate() {
+                        component[x] = p[i];
+                    }
+                    write_hdr_rle(&component, &mut scanline);
+                }
+            } else {
+                scanline.extend(rgbe.iter().flatten());
+            }
+            out.write_all(&scanline)?;
+        }
+        Ok(())
+    }
+}
+
+impl ImageWriter for PfmWriter {
+    fn write(&self, image: &Framebuffer, out: &mut dyn Write) -> io::Result<()> {
+        check_not_empty(image)?;
+        // a negative scale means little endian data
+        write!(out, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+
+        // rows are stored from the bottom of the image up
+        let mut data = Vec::with_capacity(12 * image.width() * image.height());
+        for row in image.pixels().chunks(image.width()).rev() {
+            for c in row {
+                data.extend_from_slice(&(c.r() as f32).to_le_bytes());
+                data.extend_from_slice(&(c.g() as f32).to_le_bytes());
+                data.extend_from_slice(&(c.b() as f32).to_le_bytes());
+            }
+        }
+        out.write_all(&data)
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Hdr,
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_extension(path: &Path) -> Option<ImageFormat> {
+        ImageFormat::from_name(path.extension()?.to_str()?)
+    }
+    // the usual file extension of the format, in any case
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "hdr" => Some(ImageFormat::Hdr),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+    pub fn writer(&self) -> Box<dyn ImageWriter> {
+        match self {
+            ImageFormat::Ppm => Box::new(PpmWriter),
+            ImageFormat::Png => Box::new(PngWriter),
+            ImageFormat::Hdr => Box::new(HdrWriter),
+            ImageFormat::Pfm => Box::new(PfmWriter),
+            ImageFormat::Exr => Box::new(ExrWriter::default()),
+        }
+    }
+}
+
+impl Framebuffer {
+    pub fn write_to(&self, out: &mut dyn Write, writer: &dyn ImageWriter) -> io::Result<()> {
+        writer.write(self, out)
+    }
+    // writes the image to a file, picking the format from the file extension
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let format = ImageFormat::from_extension(path).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("unsupported image format: {}", path.display()),
+        ))?;
+        self.save_as(path, format)
+    }
+    pub fn save_as(&self, path: &Path, format: ImageFormat) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out, format.writer().as_ref())?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{to_rgbe, write_hdr_rle, HdrWriter, ImageWriter, PfmWriter, PngWriter, PpmWriter};
+    use crate::{color::RGBColor, framebuffer::Framebuffer};
+
+    fn small_image() -> Framebuffer {
+        let mut image = Framebuffer::new(2, 2);
+        image.set(0, 0, RGBColor::new(1.0, 0.0, 0.25));
+        image.set(1, 1, RGBColor::new(0.5, 2.0, 0.0));
+        image
+    }
+    #[test]
+    fn ppm_test() {
+        let mut out = Vec::new();
+        PpmWriter.write(&small_image(), &mut out).unwrap();
+
+        let mut res = b"P6\n2 2\n255\n".to_vec();
+        res.extend_from_slice(&[255, 0, 128, 0, 0, 0, 0, 0, 0, 181, 255, 0]);
+        assert_eq!(res, out);
+    }
+    #[test]
+    fn png_test() {
+        let mut out = Vec::new();
+        PngWriter.write(&small_image(), &mut out).unwrap();
+
+        let mut reader = png::Decoder::new(out.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        assert_eq!(vec![255, 0, 128, 0, 0, 0, 0, 0, 0, 181, 255, 0], data);
+    }
+    #[test]
+    fn pfm_test() {
+        let mut out = Vec::new();
+        PfmWriter.write(&small_image(), &mut out).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(header.len() + 4 * 12, out.len());
+        // the first stored pixel is the bottom left one
+        assert_eq!(0.0f32.to_le_bytes(), out[header.len()..header.len() + 4]);
+        assert_eq!(0.5f32.to_le_bytes(), out[header.len() + 12..header.len() + 16]);
+    }
+    #[test]
+    fn empty_test() {
+        for image in [Framebuffer::new(0, 2), Framebuffer::new(2, 0)] {
+            for writer in [&HdrWriter as &dyn ImageWriter, &PfmWriter] {
+                let mut out = Vec::new();
+                assert_eq!(std::io::ErrorKind::InvalidInput, writer.write(&image, &mut out).unwrap_err().kind());
+                assert!(out.is_empty());
+            }
+        }
+    }
+    #[test]
+    fn rgbe_test() {
+        assert_eq!([128, 64, 0, 129], to_rgbe(1.0, 0.5, 0.0));
+        assert_eq!([0, 0, 0, 0], to_rgbe(0.0, 0.0, 0.0));
+    }
+    #[test]
+    fn hdr_rle_test() {
+        let mut out = Vec::new();
+        write_hdr_rle(&[1, 2, 3, 7, 7, 7, 7, 7, 4], &mut out);
+
+        assert_eq!(vec![3, 1, 2, 3, 133, 7, 1, 4], out);
+    }
+}
@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable, Shape}, ray::Ray, transform::{AnimatedTransform, Transform}, vector3::Vector3};
+
+// how an instance's transform changes over the times of the rays, which go from 0 to 1 over
+// the motion
+#[derive(Debug,Clone)]
+pub enum Motion {
+    Fixed(Transform),
+    // moved by `offset` times the ray's time after the transform
+    Linear(Transform, Vector3),
+    Animated(Box<AnimatedTransform>),
+}
+
+impl Motion {
+    pub fn at(&self, time: f64) -> Transform {
+        match self {
+            Motion::Fixed(transform) => *transform,
+            Motion::Linear(transform, offset) => Transform::translate(*offset * time) * *transform,
+            Motion::Animated(animated) => animated.at(time),
+        }
+    }
+    // around the whole motion between times 0 and 1
+    fn bounding_box(&self, b: &Aabb) -> Aabb {
+        match self {
+            Motion::Fixed(transform) => transform.bounding_box(b),
+            Motion::Linear(transform, offset) => {
+                let start = transform.bounding_box(b);
+                start.union(&Aabb::new(start.min + *offset, start.max + *offset))
+            }
+            Motion::Animated(animated) => animated.bounding_box(b),
+        }
+    }
+}
+
+// a shape placed in the world by a transform from its own space. The shape is shared, so a
+// mesh can be placed many times while its triangles and bvh are stored once
+pub struct Instance {
+    shape: Arc<Shape>,
+    motion: Motion,
+    bounds: Aabb,
+}
+
+impl Instance {
+    pub fn new(shape: Arc<Shape>, transform: Transform) -> Self {
+        Instance::moving(shape, Motion::Fixed(transform))
+    }
+    pub fn moving(shape: Arc<Shape>, motion: Motion) -> Self {
+        let bounds = motion.bounding_box(&shape.bounding_box());
+        Instance {shape, motion, bounds}
+    }
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+    pub fn motion(&self) -> &Motion {
+        &self.motion
+    }
+    pub fn bounding_box(&self) -> Aabb {
+        self.bounds
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let transform = self.motion.at(ray.time);
+        // the direction isn't normalized in object space, so distances along both rays match
+        let inverse = transform.inverse();
+        let local = Ray::with_time(inverse.point(ray.origin), inverse.vector(ray.direction), ray.time);
+        let mut hit_rec = self.shape.hit(&local, t_min, t_max)?;
+
+        // the inverse transpose keeps which side of the surface the ray came from
+        hit_rec.point = transform.point(hit_rec.point);
+        hit_rec.normal = transform.normal(hit_rec.normal).unit();
+        Some(hit_rec)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{Instance, Motion};
+    use crate::{color::RGBColor, hittable::{Hittable, Shape}, material::Material, ray::Ray, transform::Transform, vector3::Vector3};
+
+    #[test]
+    fn instance_test() {
+        let material = Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into());
+        let unit = Arc::new(Shape::Sphere {center: Vector3::new(0.0, 0.0, 0.0), radius: 1.0, material: material.clone()});
+        let moved = Instance::new(unit.clone(), Transform::translate(Vector3::new(0.0, 0.0, -5.0)) * Transform::scale(Vector3::new(2.0, 2.0, 2.0)));
+        let direct = Shape::Sphere {center: Vector3::new(0.0, 0.0, -5.0), radius: 2.0, material};
+
+        let ray = Ray::new(Vector3::new(0.5, 0.3, 0.0), Vector3::new(0.1, 0.2, -1.0));
+        let (a, b) = (moved.hit(&ray, 0.001, f64::INFINITY).unwrap(), direct.hit(&ray, 0.001, f64::INFINITY).unwrap());
+        assert!((a.t - b.t).abs() < 1e-9);
+        assert!((a.point - b.point).length() < 1e-9);
+        assert!((a.normal - b.normal).length() < 1e-9);
+        assert_eq!(a.front_face, b.front_face);
+
+        // the bounds follow the transform, and a mirrored instance is still seen from outside
+        let bounds = moved.bounding_box();
+        assert!((bounds.min - Vector3::new(-2.0, -2.0, -7.0)).length() < 1e-9);
+        let mirrored = Instance::new(unit, Transform::translate(Vector3::new(0.0, 0.0, -5.0)) * Transform::scale(Vector3::new(-1.0, 1.0, 1.0)));
+        let rec = mirrored.hit(&Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0)), 0.001, f64::INFINITY).unwrap();
+        assert!(rec.front_face);
+        assert!((rec.normal - Vector3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+    #[test]
+    fn motion_test() {
+        let material = Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into());
+        let unit = Arc::new(Shape::Sphere {center: Vector3::new(0.0, 0.0, 0.0), radius: 1.0, material});
+        let moving = Instance::moving(unit, Motion::Linear(Transform::identity(), Vector3::new(4.0, 0.0, 0.0)));
+
+        // only rays traced when the sphere passes through them hit it
+        let ray = |time: f64| Ray::with_time(Vector3::new(3.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0), time);
+        assert!(moving.hit(&ray(0.0), 0.001, f64::INFINITY).is_none());
+        let rec = moving.hit(&ray(0.75), 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert!((moving.bounding_box().max - Vector3::new(5.0, 1.0, 1.0)).length() < 1e-9);
+    }
+}
@@ -4,4 +4,25 @@ pub mod ray;
 pub mod hittable;
 pub mod camera;
 pub mod utils;
-pub mod material;
\ No newline at end of file
+pub mod material;
+pub mod aabb;
+pub mod bvh;
+pub mod render;
+pub mod framebuffer;
+pub mod image_writer;
+pub mod exr;
+pub mod obj;
+pub mod mesh;
+pub mod background;
+pub mod image_reader;
+pub mod light;
+pub mod texture;
+pub mod noise;
+pub mod scene;
+pub mod transform;
+pub mod instance;
+pub mod medium;
+pub mod grid;
+pub mod microfacet;
+pub mod principled;
+pub mod spectrum;
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::f64::consts::PI;
+
+use fastrand::Rng;
+
+use crate::{hittable::{Hittable, Shape}, material::Material, ray::Ray, utils::{orthonormal_basis, random_vec_in_unit_sphere}, vector3::Vector3};
+
+// point picked on a light, with the solid angle density of picking it as seen from the origin
+pub struct LightSample {
+    pub point: Vector3,
+    // outward normal of the light at the point
+    pub normal: Vector3,
+    pub pdf: f64,
+}
+
+// solid angle density of a point picked uniformly over the area of a shape
+fn area_to_solid_angle(origin: Vector3, point: Vector3, normal: Vector3, area: f64) -> f64 {
+    let to_point = point - origin;
+    let distance_squared = to_point.length_squared();
+    let cosine = f64::abs(to_point.dot(normal)) / distance_squared.sqrt();
+    if cosine < 1e-12 {
+        return 0.0
+    }
+    distance_squared / (cosine * area)
+}
+
+impl Shape {
+    pub fn material(&self) -> Option<&Material> {
+        match self {
+            Shape::Sphere {material, ..} | Shape::Plane {material, ..} | Shape::Quad {material, ..}
+            | Shape::Disk {material, ..} | Shape::Triangle {material, ..} => Some(material),
+            Shape::Mesh(_) => None,
+            Shape::Instance(instance) => instance.shape().material(),
+            Shape::Volume(volume) => Some(&volume.medium().material),
+        }
+    }
+
+    // emissive shapes that can be sampled directly
+    pub fn is_light(&self) -> bool {
+        matches!(self.material(), Some(Material::DiffuseLight(_, _)))
+            && matches!(self, Shape::Sphere {..} | Shape::Quad {..} | Shape::Triangle {..})
+    }
+
+    pub fn sample_light(&self, origin: Vector3, rng: &Rng) -> Option<LightSample> {
+        match self {
+            Shape::Sphere {center, radius, ..} => {
+                let to_center = *center - origin;
+                let distance_squared = to_center.length_squared();
+                if distance_squared <= radius * radius {
+                    // inside the sphere, every point of it is visible
+                    let normal = random_vec_in_unit_sphere(rng).unit();
+                    let point = *center + normal * *radius;
+                    let area = 4.0 * PI * radius * radius;
+                    return Some(LightSample {point, normal, pdf: area_to_solid_angle(origin, point, normal, area)})
+                }
+
+                // uniform direction in the cone the sphere subtends
+                let cos_theta_max = f64::sqrt(1.0 - radius * radius / distance_squared);
+                let cos_theta = 1.0 - rng.f64() * (1.0 - cos_theta_max);
+                let sin_theta = f64::sqrt(f64::max(0.0, 1.0 - cos_theta * cos_theta));
+                let phi = 2.0 * PI * rng.f64();
+                let w = to_center / distance_squared.sqrt();
+                let (u, v) = orthonormal_basis(w);
+                let direction = u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + w * cos_theta;
+
+                let rec = self.hit(&Ray::new(origin, direction), 0.0, f64::INFINITY)?;
+                Some(LightSample {
+                    point: rec.point,
+                    normal: (rec.point - *center) / *radius,
+                    pdf: 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+                })
+            }
+            Shape::Quad {corner, edge_u, edge_v, ..} => {
+                let n = edge_u.cross(*edge_v);
+                let point = *corner + *edge_u * rng.f64() + *edge_v * rng.f64();
+                let normal = n.unit();
+                Some(LightSample {point, normal, pdf: area_to_solid_angle(origin, point, normal, n.length())})
+            }
+            Shape::Triangle {a, b, c, ..} => {
+                // uniform barycentric coordinates
+                let s = f64::sqrt(rng.f64());
+                let (u, v) = (1.0 - s, rng.f64() * s);
+                let point = *a * (1.0 - u - v) + *b * u + *c * v;
+                let n = (*b - *a).cross(*c - *a);
+                let normal = n.unit();
+                Some(LightSample {point, normal, pdf: area_to_solid_angle(origin, point, normal, n.length() / 2.0)})
+            }
+            _ => None,
+        }
+    }
+
+    // density with which `sample_light` picks the direction, 0 if it misses the shape before `t_max`
+    pub fn light_pdf(&self, origin: Vector3, direction: Vector3, t_max: f64) -> f64 {
+        let rec = match self.hit(&Ray::new(origin, direction), 0.001, t_max) {
+            Some(rec) => rec,
+            None => return 0.0,
+        };
+        match self {
+            Shape::Sphere {center, radius, ..} => {
+                let distance_squared = (*center - origin).length_squared();
+                if distance_squared <= radius * radius {
+                    let normal = (rec.point - *center) / *radius;
+                    return area_to_solid_angle(origin, rec.point, normal, 4.0 * PI * radius * radius)
+                }
+                let cos_theta_max = f64::sqrt(1.0 - radius * radius / distance_squared);
+                1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+            }
+            Shape::Quad {edge_u, edge_v, ..} => {
+                let n = edge_u.cross(*edge_v);
+                area_to_solid_angle(origin, rec.point, n.unit(), n.length())
+            }
+            Shape::Triangle {a, b, c, ..} => {
+                let n = (*b - *a).cross(*c - *a);
+                area_to_solid_angle(origin, rec.point, n.unit(), n.length() / 2.0)
+            }
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{color::RGBColor, hittable::Shape, material::Material, vector3::Vector3};
+
+    #[test]
+    fn sample_pdf_matches_test() {
+        let light = Material::DiffuseLight(RGBColor::new(1.0, 1.0, 1.0), 1.0);
+        let shapes = [
+            Shape::Sphere {center: Vector3::new(0.0, 3.0, 0.0), radius: 1.0, material: light.clone()},
+            Shape::Sphere {center: Vector3::new(0.0, 0.5, 0.0), radius: 2.0, material: light.clone()},
+            Shape::Quad {corner: Vector3::new(-1.0, 2.0, -1.0), edge_u: Vector3::new(2.0, 0.0, 0.0), edge_v: Vector3::new(0.0, 0.5, 2.0), material: light.clone()},
+            Shape::Triangle {a: Vector3::new(-1.0, 2.0, 0.0), b: Vector3::new(1.0, 2.0, 0.0), c: Vector3::new(0.0, 3.0, 1.0), material: light},
+        ];
+        let rng = fastrand::Rng::with_seed(2);
+        let origin = Vector3::new(0.2, 0.0, 0.1);
+
+        for shape in &shapes {
+            assert!(shape.is_light());
+            for _ in 0..100 {
+                let sample = shape.sample_light(origin, &rng).unwrap();
+                let pdf = shape.light_pdf(origin, sample.point - origin, f64::INFINITY);
+                assert!((pdf - sample.pdf).abs() < 1e-6 * pdf, "{} != {}", pdf, sample.pdf);
+            }
+        }
+    }
+}
@@ -1,55 +1,68 @@
-use std::{fs::File, f32::consts::PI};
-use std::io::Write;
-use rust_ray_tracer::utils::random_scene;
-use rust_ray_tracer::{vector3::Vector3, hittable::{Shape, World}, camera::Camera, color::RGBColor, utils::ray_color, material::Material};
+mod cli;
 
-fn main() {
-    // Image
-    let rng = fastrand::Rng::new();
-    rng.seed(10);
-    let aspect_ratio = 3.0/2.0;
-    let width = 400;
-    let height = ((width as f64)/aspect_ratio) as i32;
-    let samples_per_pixel = 100;
-    let max_depth = 50;
+use std::error::Error;
+use std::process;
+use std::time::Instant;
 
-    let mut image_file = File::create("image.ppm").expect("Failed to create file");
+use cli::{Command, RenderOptions};
+use rust_ray_tracer::utils::random_scene;
+use rust_ray_tracer::{vector3::Vector3, camera::Camera, render::{RenderSettings, Renderer}, scene::Scene};
 
-    // World
-    let world = random_scene(&rng);
+fn render(options: &RenderOptions) -> Result<(), Box<dyn Error>> {
+    let (world, mut cam, mut settings) = match &options.scene {
+        Some(path) => {
+            let scene = Scene::load(path)?;
+            (scene.world, scene.camera, scene.settings)
+        }
+        None => {
+            // Image
+            let settings = RenderSettings::default();
+            let rng = fastrand::Rng::new();
+            rng.seed(settings.seed);
 
-    // Camera
-    let cam = Camera::new(
-        Vector3::new(8.0,5.0,10.0),
-        Vector3::new(0.0,0.0,0.0), 
-        Vector3::new(0.0,1.0,0.0),
-        20.0, 
-        aspect_ratio,
-        0.1,
-        10.0
-    );
+            // World
+            let mut world = random_scene(&rng);
+            world.build_bvh();
 
-    
-    // Render
+            // Camera
+            let cam = Camera::new(
+                Vector3::new(8.0,5.0,10.0),
+                Vector3::new(0.0,0.0,0.0),
+                Vector3::new(0.0,1.0,0.0),
+                20.0,
+                settings.aspect_ratio(),
+                0.1,
+                10.0
+            );
+            (world, cam, settings)
+        }
+    };
+    options.apply(&mut settings);
+    cam.set_aspect_ratio(settings.aspect_ratio());
 
-    // Write that colors are in ASCII,
-    // that there are 256 columns and 256 rows,
-    // and that the max color is 255
-    write!(image_file, "P3\n{} {}\n255\n", width, height).expect("Failed to write data");
+    // Render
+    let start = Instant::now();
+    let image = Renderer::new(settings).render(&world, &cam);
+    image.save_as(&options.output, options.format)
+        .map_err(|e| format!("{}: {}", options.output.display(), e))?;
+    eprintln!("{}: {}x{}, {} samples per pixel in {:.1}s", options.output.display(),
+              settings.width, settings.height, settings.samples_per_pixel, start.elapsed().as_secs_f64());
+    Ok(())
+}
 
-    for h in (0..height).rev() {
-        for w in 0..width {
-            let mut pixel_color = RGBColor::new(0.0, 0.0, 0.0);
-            for _s in 0..samples_per_pixel {
-                let v = (h as f64 + rng.f64()) / (height - 1) as f64;
-                let u = (w as f64 + rng.f64()) / (width - 1) as f64;
-    
-                let ray = cam.get_ray(u, v, &rng);
-                pixel_color = pixel_color + ray_color(&ray, &world, max_depth, &rng);
-                
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match cli::parse(&args) {
+        Ok(Command::Help) => print!("{}", cli::USAGE),
+        Ok(Command::Render(options)) => {
+            if let Err(e) = render(&options) {
+                eprintln!("error: {}", e);
+                process::exit(1);
             }
-
-            pixel_color.write_color(&mut image_file, samples_per_pixel);
+        }
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, cli::USAGE);
+            process::exit(2);
         }
     }
 }
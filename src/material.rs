@@ -1,22 +1,109 @@
-use crate::{color::RGBColor, ray::Ray, hittable::HitRecord, utils::random_vec_in_unit_sphere, vector3::Vector3};
+use std::f64::consts::PI;
+
+use crate::{color::RGBColor, ray::Ray, hittable::HitRecord, medium::PhaseFunction, microfacet::{fresnel_dielectric, refract, ComplexIor, Ggx}, principled::Principled, spectrum::Ior, texture::Texture, utils::random_vec_in_unit_sphere, vector3::Vector3};
 use fastrand::Rng;
 
+// direction picked by a material for the continuation of a path
+pub struct ScatterRecord {
+    pub ray: Ray,
+    // bsdf times cosine divided by the pdf, what the light coming back along `ray` is multiplied by
+    pub attenuation: RGBColor,
+    // solid angle density of the direction, none for specular reflection and refraction,
+    // which only ever scatter in one direction
+    pub pdf: Option<f64>,
+}
+
 pub trait LightReaction {
-    fn scatter(&self, rng: &Rng, r_in: &Ray, rec: &HitRecord) -> Option<Ray>;
+    // picks a direction to continue a path arriving along `r_in`, none if the path ends here
+    fn sample(&self, rng: &Rng, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+    // bsdf times the cosine with the normal, for light arriving from `direction` and leaving
+    // back along `r_in`. Specular materials give black since their directions can't be hit at random
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> RGBColor;
+    // density with which `sample` picks `direction`
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> f64;
 }
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub enum Material {
-    Lambertian(RGBColor),
-    Metal(RGBColor, f64),
-    Dielectric(RGBColor, f64),
+    Lambertian(Texture),
+    Metal(Texture, f64),
+    // glass: index of refraction, and absorption of the light going through it per unit length
+    Dielectric(Ior, RGBColor),
+    // emits color * strength from its front face and doesn't scatter
+    DiffuseLight(RGBColor, f64),
+    // the material underneath, with its shading normal tilted along the slopes of the luminance
+    // of a texture times the strength
+    Bump(Box<Material>, Texture, f64),
+    // inside participating media: scatters by the phase function, keeping the albedo of the light
+    Medium(Texture, PhaseFunction),
+    // metal with a GGX distribution of microfacets, by its index of refraction and a roughness
+    // from 0 to 1
+    Conductor(ComplexIor, f64),
+    // glass with a GGX distribution of microfacets: index of refraction, roughness and absorption
+    RoughDielectric(f64, f64, RGBColor),
+    Principled(Box<Principled>),
+}
+
+// step of the finite differences giving the slopes of bump textures
+const BUMP_DELTA: f64 = 1e-4;
+
+// swaps bump mapped materials of the record for the material underneath, tilting the shading
+// normal on the way
+pub fn apply_bump(rec: &mut HitRecord) {
+    while let Material::Bump(base, height, strength) = rec.material {
+        let gradient = {
+            let h = |offset: Vector3| height.value(rec.u, rec.v, rec.point + offset).luminance();
+            let (dx, dy, dz) = (Vector3::new(BUMP_DELTA, 0.0, 0.0), Vector3::new(0.0, BUMP_DELTA, 0.0), Vector3::new(0.0, 0.0, BUMP_DELTA));
+            Vector3::new(h(dx) - h(-dx), h(dy) - h(-dy), h(dz) - h(-dz)) / (2.0 * BUMP_DELTA)
+        };
+        let normal = rec.normal - (gradient - rec.normal * gradient.dot(rec.normal)) * *strength;
+        if !normal.near_zero() {
+            rec.normal = normal.unit();
+        }
+        rec.material = base;
+    }
 }
 
 impl Material {
-    pub fn attenuation(&self) -> RGBColor {
+    // the material under any bump mapping
+    pub fn base(&self) -> &Material {
+        match self {
+            Material::Bump(base, _, _) => base.base(),
+            _ => self,
+        }
+    }
+    pub fn emitted(&self, rec: &HitRecord) -> RGBColor {
         match self {
-            Material::Lambertian(attenuation) => *attenuation,
-            Material::Metal (attenuation, _fuzz) => *attenuation,
-            Material::Dielectric(attenuation, _refrac_index) => *attenuation,
+            Material::DiffuseLight(color, strength) if rec.front_face => *color * *strength,
+            Material::Bump(base, _, _) => base.emitted(rec),
+            _ => RGBColor::new(0.0, 0.0, 0.0),
+        }
+    }
+    // absorption per unit length of a medium leaving `color` of the light going through
+    // `distance` units of it
+    pub fn absorption(color: RGBColor, distance: f64) -> RGBColor {
+        RGBColor::new(-color.r().ln(), -color.g().ln(), -color.b().ln()) * (1.0 / distance)
+    }
+    // light left after going through `distance` units of a medium absorbing `absorption` per
+    // unit length, by the Beer-Lambert law
+    pub fn transmittance(absorption: RGBColor, distance: f64) -> RGBColor {
+        RGBColor::new(f64::exp(-absorption.r() * distance), f64::exp(-absorption.g() * distance), f64::exp(-absorption.b() * distance))
+    }
+    // absorption of what a ray scattered in `direction` goes through: the glass it went into
+    // through a front face, nothing once out through a back face, and else the same as before.
+    // Leaving glass nested in other glass takes the ray out to empty space
+    pub fn absorption_after(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> Option<RGBColor> {
+        let absorption = match self.base() {
+            Material::Dielectric(_, absorption) | Material::RoughDielectric(_, _, absorption) => *absorption,
+            Material::Principled(_) => RGBColor::new(0.0, 0.0, 0.0),
+            _ => return r_in.absorption,
+        };
+        // reflected
+        if direction.dot(rec.normal) >= 0.0 {
+            return r_in.absorption
+        }
+        match rec.front_face && absorption != RGBColor::new(0.0, 0.0, 0.0) {
+            true => Some(absorption),
+            false => None,
         }
     }
     fn reflectance(&self, cosine: f64, ref_idx: f64) -> f64 {
@@ -29,9 +116,9 @@ impl Material {
 
 
 impl LightReaction for Material {
-    fn scatter(&self, rng: &Rng, r_in: &Ray, rec: &HitRecord) -> Option<Ray> {
+    fn sample(&self, rng: &Rng, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
         match self {
-            Material::Lambertian(_) => {
+            Material::Lambertian(albedo) => {
                 // lambertian material always scatters the ray and attenuate by its reflectance
                 let mut scatter_direction = rec.normal + random_vec_in_unit_sphere(rng).unit();
 
@@ -39,29 +126,38 @@ impl LightReaction for Material {
                 if scatter_direction.near_zero() {
                     scatter_direction = rec.normal;
                 }
-                let scattered = Ray::new(rec.point, scatter_direction);
-                return Some(scattered)
+                // cosine weighted, so the cosine and pdf cancel out
+                let pdf = f64::max(0.0, scatter_direction.unit().dot(rec.normal)) / PI;
+                let attenuation = albedo.value(rec.u, rec.v, rec.point);
+                Some(ScatterRecord {ray: Ray::with_time(rec.point, scatter_direction, r_in.time), attenuation, pdf: Some(pdf)})
                 
             },
-            Material::Metal(_, fuzz) => {
+            Material::Metal(attenuation, fuzz) => {
                 // the ray isnt randomly scattered, but is reflected
                 let reflected = r_in.direction.unit().reflect(rec.normal);
-                let scattered = Ray::new(rec.point, reflected + random_vec_in_unit_sphere(rng)*(*fuzz));
+                let scattered = Ray::with_time(rec.point, reflected + random_vec_in_unit_sphere(rng)*(*fuzz), r_in.time);
 
+                // the fuzzed reflection has no closed form density, so light sampling
+                // treats it like a mirror
                 if scattered.direction.dot(rec.normal) > 0.0 {
-                    return Some(scattered)    
+                    Some(ScatterRecord {ray: scattered, attenuation: attenuation.value(rec.u, rec.v, rec.point), pdf: None})
                 }
                 else {
-                    return None
+                    None
                 }
                 
                 
             }
-            Material::Dielectric(_, refrac_index) => {
+            Material::Dielectric(ior, _) => {
+                // spectral rays are split by dispersion, keeping only the hero wavelength
+                let (refrac_index, wavelengths) = match r_in.wavelengths {
+                    Some(wavelengths) if ior.is_dispersive() => (ior.at(wavelengths.hero()), Some(wavelengths.dispersed())),
+                    _ => (ior.mean(), None),
+                };
                 // always refracts
                 let refraction_ratio = match rec.front_face {
-                    true => 1.0/(*refrac_index),
-                    false => *refrac_index,
+                    true => 1.0/refrac_index,
+                    false => refrac_index,
                 };
 
                 let unit_direction = r_in.direction.unit();
@@ -77,11 +173,214 @@ impl LightReaction for Material {
                 };
                 
 
-                let scattered = Ray::new(rec.point, direction);
+                let mut scattered = Ray::with_time(rec.point, direction, r_in.time);
+                scattered.wavelengths = wavelengths;
                 
-                Some(scattered)
+                Some(ScatterRecord {ray: scattered, attenuation: RGBColor::new(1.0, 1.0, 1.0), pdf: None})
             },
-            
+            Material::DiffuseLight(_, _) => None,
+            Material::Bump(base, _, _) => base.sample(rng, r_in, rec),
+            Material::Medium(albedo, phase) => {
+                // sampled exactly by the phase function, which cancels out
+                let forward = r_in.direction.unit();
+                let direction = phase.sample(rng, forward);
+                let pdf = phase.value(direction.dot(forward));
+                let scattered = Ray::with_time(rec.point, direction, r_in.time);
+                Some(ScatterRecord {ray: scattered, attenuation: albedo.value(rec.u, rec.v, rec.point), pdf: Some(pdf)})
+            }
+            Material::Conductor(ior, roughness) => {
+                // reflected off a facet seen from the ray, which leaves only the fresnel term and
+                // the shadowing of the reflection
+                let ggx = Ggx::new(*roughness);
+                let wo = -r_in.direction.unit();
+                let cos_o = wo.dot(rec.normal);
+                if cos_o <= 0.0 {
+                    return None
+                }
+                let m = ggx.sample_visible(rng, rec.normal, wo);
+                let direction = (-wo).reflect(m);
+                let cos_i = direction.dot(rec.normal);
+                if cos_i <= 0.0 {
+                    return None
+                }
+                let attenuation = ior.fresnel(wo.dot(m)) * (ggx.g2(cos_o, cos_i) / ggx.g1(cos_o));
+                let pdf = ggx.visible_pdf(rec.normal, wo, m) / (4.0 * wo.dot(m));
+                Some(ScatterRecord {ray: Ray::with_time(rec.point, direction, r_in.time), attenuation, pdf: Some(pdf)})
+            }
+            Material::RoughDielectric(refrac_index, roughness, _) => {
+                // reflected or refracted by a facet seen from the ray, in proportion to the fresnel term
+                let ggx = Ggx::new(*roughness);
+                let eta = match rec.front_face {
+                    true => *refrac_index,
+                    false => 1.0 / *refrac_index,
+                };
+                let wo = -r_in.direction.unit();
+                let cos_o = wo.dot(rec.normal);
+                if cos_o <= 0.0 {
+                    return None
+                }
+                let m = ggx.sample_visible(rng, rec.normal, wo);
+                let direction = match rng.f64() < fresnel_dielectric(wo.dot(m), eta) {
+                    true => (-wo).reflect(m),
+                    false => refract(wo, m, eta)?,
+                };
+                // reflections below the surface or refractions above it are shadowed
+                if (direction.dot(rec.normal) > 0.0) != (direction.dot(m) > 0.0) {
+                    return None
+                }
+                let pdf = ggx.dielectric(eta, rec.normal, wo, direction).1;
+                let g = ggx.g2(cos_o, direction.dot(rec.normal)) / ggx.g1(cos_o);
+                Some(ScatterRecord {ray: Ray::with_time(rec.point, direction, r_in.time), attenuation: RGBColor::new(g, g, g), pdf: Some(pdf)})
+            }
+            Material::Principled(principled) => principled.sample(rng, r_in, rec),
+        }
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> RGBColor {
+        match self.base() {
+            Material::Lambertian(albedo) => albedo.value(rec.u, rec.v, rec.point) * (f64::max(0.0, direction.unit().dot(rec.normal)) / PI),
+            // no cosine term in media
+            Material::Medium(albedo, phase) => albedo.value(rec.u, rec.v, rec.point) * phase.value(direction.unit().dot(r_in.direction.unit())),
+            Material::Conductor(ior, roughness) => Ggx::new(*roughness).conductor(ior, rec.normal, -r_in.direction.unit(), direction.unit()).0,
+            Material::RoughDielectric(refrac_index, roughness, _) => {
+                let eta = if rec.front_face { *refrac_index } else { 1.0 / *refrac_index };
+                let value = Ggx::new(*roughness).dielectric(eta, rec.normal, -r_in.direction.unit(), direction.unit()).0;
+                RGBColor::new(value, value, value)
+            }
+            Material::Principled(principled) => principled.eval(r_in, rec, direction),
+            _ => RGBColor::new(0.0, 0.0, 0.0),
+        }
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> f64 {
+        match self.base() {
+            Material::Lambertian(_) => f64::max(0.0, direction.unit().dot(rec.normal)) / PI,
+            Material::Medium(_, phase) => phase.value(direction.unit().dot(r_in.direction.unit())),
+            Material::Conductor(ior, roughness) => Ggx::new(*roughness).conductor(ior, rec.normal, -r_in.direction.unit(), direction.unit()).1,
+            Material::RoughDielectric(refrac_index, roughness, _) => {
+                let eta = if rec.front_face { *refrac_index } else { 1.0 / *refrac_index };
+                Ggx::new(*roughness).dielectric(eta, rec.normal, -r_in.direction.unit(), direction.unit()).1
+            }
+            Material::Principled(principled) => principled.pdf(r_in, rec, direction),
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{apply_bump, LightReaction, Material};
+    use crate::{color::RGBColor, hittable::HitRecord, microfacet::ComplexIor, ray::Ray, texture::{NoisePattern, NoiseTexture, Texture}, vector3::Vector3};
+
+    #[test]
+    fn sample_eval_pdf_test() {
+        let mut rec = HitRecord::new();
+        rec.normal = Vector3::new(0.0, 1.0, 0.0);
+        rec.front_face = true;
+        let r_in = Ray::new(Vector3::new(1.0, 1.0, 0.0), Vector3::new(-1.0, -1.0, 0.0));
+        let rng = fastrand::Rng::with_seed(4);
+
+        let material = Material::Lambertian(RGBColor::new(0.2, 0.4, 0.8).into());
+        for _ in 0..100 {
+            let scattered = material.sample(&rng, &r_in, &rec).unwrap();
+            let pdf = material.pdf(&r_in, &rec, scattered.ray.direction);
+            let weight = material.eval(&r_in, &rec, scattered.ray.direction) * (1.0 / pdf);
+            assert!((scattered.pdf.unwrap() - pdf).abs() < 1e-9);
+            assert!((weight.b() - scattered.attenuation.b()).abs() < 1e-9);
+        }
+
+        // mirrors only reflect in one direction
+        let scattered = Material::Metal(RGBColor::new(1.0, 1.0, 1.0).into(), 0.0).sample(&rng, &r_in, &rec).unwrap();
+        assert!(scattered.pdf.is_none());
+        assert!((scattered.ray.direction.unit().y - f64::sqrt(0.5)).abs() < 1e-9);
+    }
+    #[test]
+    fn microfacet_test() {
+        let rng = fastrand::Rng::with_seed(12);
+        let r_in = Ray::new(Vector3::new(0.5, 1.0, 0.0), Vector3::new(-0.5, -1.0, 0.2));
+        let materials = [
+            Material::Conductor(ComplexIor::GOLD, 0.4),
+            Material::RoughDielectric(1.5, 0.3, RGBColor::new(0.0, 0.0, 0.0)),
+            Material::RoughDielectric(1.5, 0.8, RGBColor::new(0.0, 0.0, 0.0)),
+        ];
+        for material in &materials {
+            for front_face in [true, false] {
+                let mut rec = HitRecord::new();
+                rec.normal = Vector3::new(0.0, 1.0, 0.0);
+                rec.front_face = front_face;
+                let (mut count, mut transmitted) = (0, 0);
+                for _ in 0..2000 {
+                    let scattered = match material.sample(&rng, &r_in, &rec) {
+                        Some(scattered) => scattered,
+                        None => continue,
+                    };
+                    // the weight of a sample is its bsdf over its density, both agreeing with the sampling
+                    let direction = scattered.ray.direction;
+                    let pdf = material.pdf(&r_in, &rec, direction);
+                    let weight = material.eval(&r_in, &rec, direction) * (1.0 / pdf);
+                    assert!((scattered.pdf.unwrap() - pdf).abs() < 1e-6 * pdf, "{} {}", scattered.pdf.unwrap(), pdf);
+                    assert!((weight.g() - scattered.attenuation.g()).abs() < 1e-6, "{:?} {:?}", weight, scattered.attenuation);
+                    assert!(scattered.attenuation.g() <= 1.0 + 1e-9);
+                    count += 1;
+                    transmitted += (direction.y < 0.0) as usize;
+                }
+                // most light goes through glass, and none through metal
+                assert!(count > 1000);
+                match material {
+                    Material::Conductor(_, _) => assert_eq!(0, transmitted),
+                    _ => assert!(transmitted > count / 2),
+                }
+            }
         }
     }
+    #[test]
+    fn absorption_test() {
+        let rng = fastrand::Rng::with_seed(5);
+        let glass = Material::Dielectric(1.5.into(), RGBColor::new(0.5, 0.1, 0.0));
+        let mut rec = HitRecord::new();
+        rec.normal = Vector3::new(0.0, 0.0, -1.0);
+        rec.t = 2.0;
+
+        // rays going into the glass go through its absorption, until they leave it
+        let mut r_in = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 2.0));
+        let (inward, outward) = (Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        rec.front_face = true;
+        assert_eq!(RGBColor::new(1.0, 1.0, 1.0), glass.sample(&rng, &r_in, &rec).unwrap().attenuation);
+        assert_eq!(Some(RGBColor::new(0.5, 0.1, 0.0)), glass.absorption_after(&r_in, &rec, inward));
+        assert_eq!(None, glass.absorption_after(&r_in, &rec, outward));
+        r_in.absorption = Some(RGBColor::new(0.5, 0.1, 0.0));
+        rec.front_face = false;
+        assert_eq!(None, glass.absorption_after(&r_in, &rec, inward));
+        assert_eq!(r_in.absorption, glass.absorption_after(&r_in, &rec, outward));
+        // and bounces off what's inside it
+        let diffuse = Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into());
+        assert_eq!(r_in.absorption, diffuse.absorption_after(&r_in, &rec, inward));
+    }
+    #[test]
+    fn bump_test() {
+        let base = Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into());
+        let flat = Material::Bump(Box::new(base.clone()), RGBColor::new(1.0, 1.0, 1.0).into(), 1.0);
+        let mut rec = HitRecord::new();
+        rec.normal = Vector3::new(0.0, 1.0, 0.0);
+        rec.point = Vector3::new(0.3, 0.0, 0.7);
+        rec.material = &flat;
+        apply_bump(&mut rec);
+
+        // a constant height doesn't tilt anything
+        assert_eq!(Vector3::new(0.0, 1.0, 0.0), rec.normal);
+        assert!(matches!(rec.material, Material::Lambertian(_)));
+
+        // a height growing along x tilts the normal towards -x
+        let rng = fastrand::Rng::with_seed(7);
+        let noise = NoiseTexture::new(&rng, NoisePattern::Perlin, 1.0, RGBColor::new(0.0, 0.0, 0.0).into(), RGBColor::new(1.0, 1.0, 1.0).into());
+        let ramp = Texture::Noise(Arc::new(noise));
+        let bumped = Material::Bump(Box::new(base), ramp.clone(), 0.5);
+        let slope = (ramp.value(0.0, 0.0, Vector3::new(0.31, 0.0, 0.7)).luminance() - ramp.value(0.0, 0.0, Vector3::new(0.29, 0.0, 0.7)).luminance()) / 0.02;
+        rec.normal = Vector3::new(0.0, 1.0, 0.0);
+        rec.material = &bumped;
+        apply_bump(&mut rec);
+
+        assert!((rec.normal.length() - 1.0).abs() < 1e-9);
+        assert!((rec.normal.x / rec.normal.y + 0.5 * slope).abs() < 1e-3);
+    }
 }
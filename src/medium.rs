@@ -0,0 +1,186 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use fastrand::Rng;
+
+use crate::{aabb::Aabb, hittable::{HitRecord, Hittable, Shape}, material::Material, ray::Ray, texture::Texture,
+            utils::orthonormal_basis, vector3::Vector3};
+
+// distribution of the directions light scatters to inside a medium
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum PhaseFunction {
+    Isotropic,
+    // with the mean cosine of the scattering angle, in (-1, 1): positive scatters forward,
+    // negative backward
+    HenyeyGreenstein(f64),
+}
+
+impl PhaseFunction {
+    // density of scattering by an angle of cosine `cos` with the direction of travel
+    pub fn value(&self, cos: f64) -> f64 {
+        match self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let denom = 1.0 + g * g - 2.0 * g * cos;
+                (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+            }
+        }
+    }
+
+    // new direction of travel for light going along `direction`, which must be unit length
+    pub fn sample(&self, rng: &Rng, direction: Vector3) -> Vector3 {
+        let cos = match self {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() > 1e-3 => {
+                let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * rng.f64());
+                (1.0 + g * g - s * s) / (2.0 * g)
+            }
+            _ => 1.0 - 2.0 * rng.f64(),
+        };
+        let cos = cos.clamp(-1.0, 1.0);
+        let sin = f64::sqrt(1.0 - cos * cos);
+        let phi = 2.0 * PI * rng.f64();
+        let (u, v) = orthonormal_basis(direction);
+        u * (sin * phi.cos()) + v * (sin * phi.sin()) + direction * cos
+    }
+}
+
+// homogeneous participating medium, like fog or smoke
+#[derive(Clone)]
+pub struct Medium {
+    // interactions per unit length
+    pub density: f64,
+    // `Material::Medium`, scattering with the albedo and phase function at interactions
+    pub material: Material,
+}
+
+impl Medium {
+    // the albedo is the fraction of light scattered rather than absorbed at interactions
+    pub fn new(density: f64, albedo: Texture, phase: PhaseFunction) -> Self {
+        Medium {density, material: Material::Medium(albedo, phase)}
+    }
+    // fraction of light going through `distance` units of the medium
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        f64::exp(-self.density * distance)
+    }
+    // distance travelled to the next interaction, for `u` uniform in [0, 1)
+    pub fn free_path(&self, u: f64) -> f64 {
+        -f64::ln(1.0 - u) / self.density
+    }
+    // record of an interaction at `t` along the ray, whose normal only faces the ray back
+    pub fn interaction(&self, ray: &Ray, t: f64) -> HitRecord<'_> {
+        let mut rec = HitRecord::new();
+        rec.t = t;
+        rec.point = ray.at(t);
+        rec.normal = -ray.direction.unit();
+        rec.front_face = true;
+        rec.material = &self.material;
+        rec
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// number in [0, 1) hashed from the bits of the ray. Hits don't get random numbers, but every
+// ray of a path already starts at a random place or goes in a random direction
+fn ray_random(ray: &Ray) -> f64 {
+    let values = [ray.origin.x, ray.origin.y, ray.origin.z, ray.direction.x, ray.direction.y, ray.direction.z, ray.time];
+    let hash = values.iter().fold(0x9e3779b97f4a7c15, |h, x| mix(h ^ x.to_bits()));
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// medium filling a shape, which must be closed and convex. Rays are hit at a random distance
+// into it, so shadow rays are blocked with the probability the medium absorbs their light
+pub struct Volume {
+    boundary: Arc<Shape>,
+    medium: Medium,
+}
+
+impl Volume {
+    pub fn new(boundary: Arc<Shape>, medium: Medium) -> Self {
+        Volume {boundary, medium}
+    }
+    pub fn medium(&self) -> &Medium {
+        &self.medium
+    }
+    pub fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+impl Hittable for Volume {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // along the whole line, for rays starting inside
+        let enter = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(ray, enter.t + 1e-4, f64::INFINITY)?;
+        let start = f64::max(enter.t, t_min);
+        let end = f64::min(exit.t, t_max);
+        if start >= end {
+            return None
+        }
+
+        let t = start + self.medium.free_path(ray_random(ray)) / ray.direction.length();
+        match t < end {
+            true => Some(self.medium.interaction(ray, t)),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{Medium, PhaseFunction, Volume};
+    use crate::{color::RGBColor, hittable::{Hittable, Shape}, material::Material, ray::Ray, utils::random_vec_in_unit_sphere, vector3::Vector3};
+
+    #[test]
+    fn phase_test() {
+        let rng = fastrand::Rng::with_seed(4);
+        let forward = Vector3::new(0.0, 0.0, 1.0);
+        for phase in [PhaseFunction::Isotropic, PhaseFunction::HenyeyGreenstein(0.6), PhaseFunction::HenyeyGreenstein(-0.3)] {
+            // integrates to one over the sphere
+            let n = 20000;
+            let integral: f64 = (0..n).map(|i| phase.value(-1.0 + 2.0 * (i as f64 + 0.5) / n as f64)).sum::<f64>()
+                * 2.0 * std::f64::consts::PI * 2.0 / n as f64;
+            assert!((integral - 1.0).abs() < 1e-3, "{:?}", phase);
+
+            // the mean cosine of the samples is the anisotropy
+            let mean = (0..n).map(|_| phase.sample(&rng, forward).dot(forward)).sum::<f64>() / n as f64;
+            let g = match phase {
+                PhaseFunction::HenyeyGreenstein(g) => g,
+                PhaseFunction::Isotropic => 0.0,
+            };
+            assert!((mean - g).abs() < 0.02, "{:?} {}", phase, mean);
+        }
+    }
+    #[test]
+    fn volume_test() {
+        let rng = fastrand::Rng::with_seed(9);
+        let boundary = Shape::Sphere {center: Vector3::new(0.0, 0.0, 0.0), radius: 1.0, material: Material::Lambertian(RGBColor::new(0.0, 0.0, 0.0).into())};
+        let medium = Medium::new(0.5, RGBColor::new(1.0, 1.0, 1.0).into(), PhaseFunction::Isotropic);
+        let volume = Volume::new(Arc::new(boundary), medium.clone());
+
+        // rays through the middle go through 2 units of medium
+        let n = 20000;
+        let passed = (0..n).filter(|_| {
+            let jitter = random_vec_in_unit_sphere(&rng) * 1e-3;
+            let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0) + jitter, Vector3::new(0.0, 0.0, -2.0));
+            match volume.hit(&ray, 0.001, f64::INFINITY) {
+                Some(rec) => {
+                    assert!(rec.point.length() <= 1.0 + 1e-6);
+                    false
+                }
+                None => true,
+            }
+        }).count();
+        assert!((passed as f64 / n as f64 - medium.transmittance(2.0)).abs() < 0.015);
+
+        // and the ones starting inside only through what's in front of them
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        assert!(volume.hit(&ray, 0.001, f64::INFINITY).is_none_or(|rec| rec.t <= 1.0));
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{aabb::Aabb, bvh::Bvh, hittable::{hit_triangle, HitRecord, Hittable}, material::Material, ray::Ray, vector3::Vector3};
+
+// triangles sharing indexed vertex buffers and a material, with their own bvh
+pub struct TriangleMesh {
+    positions: Vec<Vector3>,
+    // per vertex, empty for flat shading
+    normals: Vec<Vector3>,
+    // per vertex, empty to use the barycentric coordinates instead
+    uvs: Vec<(f64, f64)>,
+    // counter-clockwise vertices of every triangle
+    indices: Vec<[u32; 3]>,
+    material: Material,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vector3>, normals: Vec<Vector3>, uvs: Vec<(f64, f64)>,
+               indices: Vec<[u32; 3]>, material: Material) -> Self {
+        assert!(normals.is_empty() || normals.len() == positions.len(), "Mesh needs one normal per vertex");
+        assert!(uvs.is_empty() || uvs.len() == positions.len(), "Mesh needs one uv per vertex");
+        assert!(indices.iter().flatten().all(|&i| (i as usize) < positions.len()), "Mesh index out of range");
+
+        let bounds: Vec<Aabb> = indices.iter()
+            .map(|[a, b, c]| {
+                Aabb::from_points(positions[*a as usize], positions[*b as usize])
+                    .grow(positions[*c as usize])
+                    .pad(1e-4)
+            })
+            .collect();
+        let bvh = Bvh::new(&bounds);
+
+        TriangleMesh {positions, normals, uvs, indices, material, bvh}
+    }
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+    // corners of the triangle
+    pub fn triangle(&self, i: usize) -> [Vector3; 3] {
+        let [a, b, c] = self.indices[i];
+        [self.positions[a as usize], self.positions[b as usize], self.positions[c as usize]]
+    }
+
+    fn hit_one(&self, i: usize, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let [a, b, c] = self.triangle(i);
+        let (t, b1, b2) = hit_triangle(ray, a, b, c, t_min, t_max)?;
+        let b0 = 1.0 - b1 - b2;
+        let [ia, ib, ic] = self.indices[i].map(|v| v as usize);
+
+        let mut hit_rec = HitRecord::new();
+        hit_rec.t = t;
+        hit_rec.point = ray.at(t);
+        hit_rec.material = &self.material;
+        // the geometric normal decides which side was hit, the shading normal follows it
+        hit_rec.set_face_normal(ray, (b - a).cross(c - a).unit());
+        if !self.normals.is_empty() {
+            let n = self.normals[ia] * b0 + self.normals[ib] * b1 + self.normals[ic] * b2;
+            if !n.near_zero() {
+                hit_rec.normal = match hit_rec.front_face {
+                    true => n.unit(),
+                    false => -n.unit(),
+                };
+            }
+        }
+        (hit_rec.u, hit_rec.v) = match self.uvs.is_empty() {
+            true => (b1, b2),
+            false => (
+                self.uvs[ia].0 * b0 + self.uvs[ib].0 * b1 + self.uvs[ic].0 * b2,
+                self.uvs[ia].1 * b0 + self.uvs[ib].1 * b1 + self.uvs[ic].1 * b2,
+            ),
+        };
+
+        Some(hit_rec)
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, t_min, t_max, |i, closest_so_far| self.hit_one(i, ray, t_min, closest_so_far))
+            .map(|(rec, _)| rec)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TriangleMesh;
+    use crate::{color::RGBColor, hittable::{hit_triangle, Hittable}, material::Material, ray::Ray,
+                utils::random_vec_in_unit_sphere, vector3::Vector3};
+
+    // latitude/longitude sphere of radius 1 with smooth normals
+    fn uv_sphere(rings: u32, segments: u32) -> TriangleMesh {
+        let mut positions = Vec::new();
+        for r in 0..=rings {
+            let theta = std::f64::consts::PI * r as f64 / rings as f64;
+            for s in 0..segments {
+                let phi = 2.0 * std::f64::consts::PI * s as f64 / segments as f64;
+                positions.push(Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin()));
+            }
+        }
+        let mut indices = Vec::new();
+        for r in 0..rings {
+            for s in 0..segments {
+                let a = r * segments + s;
+                let b = r * segments + (s + 1) % segments;
+                indices.push([a, b, a + segments]);
+                indices.push([b, b + segments, a + segments]);
+            }
+        }
+        let normals = positions.clone();
+        TriangleMesh::new(positions, normals, Vec::new(), indices, Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into()))
+    }
+
+    #[test]
+    fn matches_brute_force_test() {
+        let mesh = uv_sphere(12, 16);
+        let rng = fastrand::Rng::with_seed(5);
+
+        for _ in 0..500 {
+            let ray = Ray::new(random_vec_in_unit_sphere(&rng) * 3.0, random_vec_in_unit_sphere(&rng));
+            let brute = (0..mesh.triangle_count())
+                .filter_map(|i| {
+                    let [a, b, c] = mesh.triangle(i);
+                    hit_triangle(&ray, a, b, c, 0.001, f64::INFINITY).map(|(t, _, _)| t)
+                })
+                .fold(None, |best: Option<f64>, t| Some(best.map_or(t, |b| f64::min(b, t))));
+
+            assert_eq!(brute, mesh.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t));
+        }
+    }
+    #[test]
+    fn smooth_normal_test() {
+        let mesh = uv_sphere(12, 16);
+        let ray = Ray::new(Vector3::new(0.3, 0.2, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let rec = mesh.hit(&ray, 0.001, f64::INFINITY).unwrap();
+
+        // the interpolated normal is close to the one of the real sphere
+        assert!(rec.front_face);
+        assert!(rec.normal.dot(rec.point.unit()) > 0.999);
+    }
+}
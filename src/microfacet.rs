@@ -0,0 +1,237 @@
+use std::f64::consts::PI;
+
+use fastrand::Rng;
+
+use crate::{color::RGBColor, utils::orthonormal_basis, vector3::Vector3};
+
+// below this the distribution is too sharp to evaluate, and perfectly smooth surfaces are
+// rendered as almost perfectly smooth ones
+const MIN_ALPHA: f64 = 1e-4;
+
+// complex index of refraction of a metal, at red, green and blue wavelengths
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct ComplexIor {
+    pub eta: RGBColor,
+    // extinction coefficient, how quickly light dies out inside the metal
+    pub k: RGBColor,
+}
+
+impl ComplexIor {
+    pub const GOLD: ComplexIor = ComplexIor::new(RGBColor::new(0.143, 0.374, 1.442), RGBColor::new(3.983, 2.385, 1.603));
+    pub const COPPER: ComplexIor = ComplexIor::new(RGBColor::new(0.200, 0.924, 1.102), RGBColor::new(3.912, 2.452, 2.142));
+    pub const ALUMINIUM: ComplexIor = ComplexIor::new(RGBColor::new(1.657, 0.880, 0.521), RGBColor::new(9.224, 6.270, 4.837));
+    pub const SILVER: ComplexIor = ComplexIor::new(RGBColor::new(0.155, 0.117, 0.138), RGBColor::new(4.828, 3.122, 2.147));
+
+    pub const fn new(eta: RGBColor, k: RGBColor) -> Self {
+        ComplexIor {eta, k}
+    }
+    // fraction of light reflected at an angle of cosine `cos` with the normal
+    pub fn fresnel(&self, cos: f64) -> RGBColor {
+        RGBColor::new(
+            fresnel_conductor(cos, self.eta.r(), self.k.r()),
+            fresnel_conductor(cos, self.eta.g(), self.k.g()),
+            fresnel_conductor(cos, self.eta.b(), self.k.b()),
+        )
+    }
+}
+
+fn fresnel_conductor(cos: f64, eta: f64, k: f64) -> f64 {
+    let cos = cos.clamp(0.0, 1.0);
+    let (cos2, sin2) = (cos * cos, 1.0 - cos * cos);
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = f64::sqrt(t0 * t0 + 4.0 * eta * eta * k * k);
+    let a = f64::sqrt(0.5 * (a2_plus_b2 + t0));
+    let (t1, t2) = (a2_plus_b2 + cos2, 2.0 * a * cos);
+    let s = (t1 - t2) / (t1 + t2);
+    let (t3, t4) = (cos2 * a2_plus_b2 + sin2 * sin2, t2 * sin2);
+    let p = s * (t3 - t4) / (t3 + t4);
+    0.5 * (s + p)
+}
+
+// fraction of light reflected by a dielectric at an angle of cosine `cos` with the normal,
+// `eta` being the index of the side light goes into over the index of the side it comes from
+pub fn fresnel_dielectric(cos: f64, eta: f64) -> f64 {
+    let cos = cos.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0
+    }
+    let cos_t = f64::sqrt(1.0 - sin2_t);
+    let s = (cos - eta * cos_t) / (cos + eta * cos_t);
+    let p = (eta * cos - cos_t) / (eta * cos + cos_t);
+    0.5 * (s * s + p * p)
+}
+
+// direction of light leaving along `wo` after refracting through a surface with normal `m`
+// facing it, none under total internal reflection
+pub fn refract(wo: Vector3, m: Vector3, eta: f64) -> Option<Vector3> {
+    let cos = wo.dot(m);
+    let sin2_t = (1.0 - cos * cos) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None
+    }
+    Some(-wo / eta + m * (cos / eta - f64::sqrt(1.0 - sin2_t)))
+}
+
+// GGX (Trowbridge-Reitz) distribution of the normals of the microscopic facets of a rough
+// surface, with Smith's masking and shadowing. Cosines are taken with the surface normal
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct Ggx {
+    alpha: f64,
+}
+
+impl Ggx {
+    // roughness from 0, smooth, to 1, with the width of the distribution its square
+    pub fn new(roughness: f64) -> Self {
+        Ggx {alpha: f64::max(roughness * roughness, MIN_ALPHA)}
+    }
+    // density of facets with normals of cosine `cos`, per unit of solid angle and projected area
+    pub fn d(&self, cos: f64) -> f64 {
+        if cos <= 0.0 {
+            return 0.0
+        }
+        let a2 = self.alpha * self.alpha;
+        let denom = cos * cos * (a2 - 1.0) + 1.0;
+        a2 / (PI * denom * denom)
+    }
+    fn lambda(&self, cos: f64) -> f64 {
+        let cos2 = cos * cos;
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        0.5 * (f64::sqrt(1.0 + self.alpha * self.alpha * tan2) - 1.0)
+    }
+    // fraction of the facets seen from a direction of cosine `cos`
+    pub fn g1(&self, cos: f64) -> f64 {
+        1.0 / (1.0 + self.lambda(cos.abs()))
+    }
+    // fraction seen from both directions, with the heights of the facets correlated
+    pub fn g2(&self, cos_o: f64, cos_i: f64) -> f64 {
+        1.0 / (1.0 + self.lambda(cos_o.abs()) + self.lambda(cos_i.abs()))
+    }
+    // density with which `sample_visible` picks the facet normal `m`
+    pub fn visible_pdf(&self, normal: Vector3, wo: Vector3, m: Vector3) -> f64 {
+        let cos_o = wo.dot(normal);
+        self.g1(cos_o) * f64::max(0.0, wo.dot(m)) * self.d(m.dot(normal)) / cos_o
+    }
+    // normal of a facet seen from `wo`, picked in proportion to its projected area (Heitz 2018)
+    pub fn sample_visible(&self, rng: &Rng, normal: Vector3, wo: Vector3) -> Vector3 {
+        let (u, v) = orthonormal_basis(normal);
+        // stretched to a hemisphere of roughness 1
+        let view = Vector3::new(self.alpha * wo.dot(u), self.alpha * wo.dot(v), wo.dot(normal)).unit();
+        let length = f64::sqrt(view.x * view.x + view.y * view.y);
+        let t1 = match length > 0.0 {
+            true => Vector3::new(-view.y, view.x, 0.0) / length,
+            false => Vector3::new(1.0, 0.0, 0.0),
+        };
+        let t2 = view.cross(t1);
+
+        // a point on the disk projected from the visible half of the hemisphere
+        let r = rng.f64().sqrt();
+        let phi = 2.0 * PI * rng.f64();
+        let (p1, p2) = (r * phi.cos(), r * phi.sin());
+        let s = 0.5 * (1.0 + view.z);
+        let p2 = (1.0 - s) * f64::sqrt(1.0 - p1 * p1) + s * p2;
+        let m = t1 * p1 + t2 * p2 + view * f64::sqrt(f64::max(0.0, 1.0 - p1 * p1 - p2 * p2));
+
+        // and unstretched
+        (u * (self.alpha * m.x) + v * (self.alpha * m.y) + normal * f64::max(0.0, m.z)).unit()
+    }
+
+    // bsdf times cosine and density of sampling light arriving from `wi` and leaving along
+    // `wo` by reflection, leaving out the fresnel term. It's taken at the cosine between `wo`
+    // and the facet normal, which comes last
+    pub fn reflection(&self, normal: Vector3, wo: Vector3, wi: Vector3) -> Option<(f64, f64, f64)> {
+        let (cos_o, cos_i) = (wo.dot(normal), wi.dot(normal));
+        if cos_o <= 0.0 || cos_i <= 0.0 {
+            return None
+        }
+        let m = (wo + wi).unit();
+        let value = self.d(m.dot(normal)) * self.g2(cos_o, cos_i) / (4.0 * cos_o);
+        Some((value, self.visible_pdf(normal, wo, m) / (4.0 * wo.dot(m)), wo.dot(m)))
+    }
+    // the same off a conductor, with the fresnel term
+    pub fn conductor(&self, ior: &ComplexIor, normal: Vector3, wo: Vector3, wi: Vector3) -> (RGBColor, f64) {
+        match self.reflection(normal, wo, wi) {
+            Some((value, pdf, cos)) => (ior.fresnel(cos) * value, pdf),
+            None => (RGBColor::new(0.0, 0.0, 0.0), 0.0),
+        }
+    }
+    // the same for a dielectric, reflecting or transmitting. The normal faces `wo`, and `eta`
+    // is the index of the other side over the index of the side of `wo`
+    pub fn dielectric(&self, eta: f64, normal: Vector3, wo: Vector3, wi: Vector3) -> (f64, f64) {
+        let (cos_o, cos_i) = (wo.dot(normal), wi.dot(normal));
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return (0.0, 0.0)
+        }
+        if let Some((value, pdf, cos)) = self.reflection(normal, wo, wi) {
+            let fresnel = fresnel_dielectric(cos, eta);
+            return (fresnel * value, fresnel * pdf)
+        }
+
+        // the facet normal refracting `wo` into `wi`, turned to the side of the surface normal
+        let m = -(wo + wi * eta);
+        if m.near_zero() {
+            return (0.0, 0.0)
+        }
+        let m = match m.dot(normal) < 0.0 {
+            true => -m.unit(),
+            false => m.unit(),
+        };
+        let (cos_om, cos_im) = (wo.dot(m), wi.dot(m));
+        if cos_om <= 0.0 || cos_im >= 0.0 {
+            return (0.0, 0.0)
+        }
+        let transmitted = 1.0 - fresnel_dielectric(cos_om, eta);
+        let denom = cos_om + eta * cos_im;
+        // change of variables from the facet normal to the refracted direction
+        let jacobian = eta * eta * -cos_im / (denom * denom);
+        let value = transmitted * self.d(m.dot(normal)) * self.g2(cos_o, cos_i) * cos_om * jacobian / cos_o;
+        (value, transmitted * self.visible_pdf(normal, wo, m) * jacobian)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fresnel_dielectric, ComplexIor, Ggx};
+    use crate::{color::RGBColor, vector3::Vector3};
+
+    #[test]
+    fn fresnel_test() {
+        // glass reflects 4% head on, and everything past the critical angle from inside
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert_eq!(1.0, fresnel_dielectric(0.5, 1.0 / 1.5));
+        // gold reflects red better than blue, and all metals reflect everything at grazing angles
+        let gold = ComplexIor::GOLD.fresnel(1.0);
+        assert!(gold.r() > 0.9 && gold.b() < 0.5);
+        assert!((ComplexIor::ALUMINIUM.fresnel(0.0).g() - 1.0).abs() < 1e-9);
+        // a metal without extinction is a dielectric
+        let fake = ComplexIor::new(RGBColor::new(1.5, 1.5, 1.5), RGBColor::new(0.0, 0.0, 0.0));
+        assert!((fake.fresnel(0.7).g() - fresnel_dielectric(0.7, 1.5)).abs() < 1e-9);
+    }
+    #[test]
+    fn ggx_test() {
+        let rng = fastrand::Rng::with_seed(3);
+        let normal = Vector3::new(0.0, 0.0, 1.0);
+        let wo = Vector3::new(0.6, 0.0, 0.8);
+        for roughness in [0.3, 0.7] {
+            let ggx = Ggx::new(roughness);
+            // the projected area of the facets is the area of the surface
+            let n = 100000;
+            let area: f64 = (0..n).map(|i| (i as f64 + 0.5) / n as f64).map(|cos| ggx.d(cos) * cos).sum::<f64>()
+                * 2.0 * std::f64::consts::PI / n as f64;
+            assert!((area - 1.0).abs() < 1e-2, "{} {}", roughness, area);
+
+            // visible normals face the viewer, and the weight of reflecting off them averages
+            // to the energy kept by the surface, which loses what bounces between facets
+            let mut weight = 0.0;
+            for _ in 0..10000 {
+                let m = ggx.sample_visible(&rng, normal, wo);
+                assert!(m.dot(wo) >= 0.0 && m.z >= 0.0);
+                let wi = (-wo).reflect(m);
+                if wi.z > 0.0 {
+                    weight += ggx.g2(wo.z, wi.z) / ggx.g1(wo.z) / 10000.0;
+                }
+            }
+            assert!(weight > 0.6 && weight <= 1.0, "{} {}", roughness, weight);
+        }
+    }
+}
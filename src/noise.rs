@@ -0,0 +1,140 @@
+use fastrand::Rng;
+
+use crate::{utils::random_vec_in_unit_sphere, vector3::Vector3};
+
+const POINT_COUNT: usize = 256;
+
+// lattice noise functions sharing one random permutation, so equal seeds give equal patterns
+pub struct Noise {
+    // unit gradients of the perlin noise
+    gradients: Vec<Vector3>,
+    // offsets in the unit cube of the worley feature points
+    features: Vec<Vector3>,
+    // shuffled 0..POINT_COUNT, twice, to hash lattice points without wrapping
+    permutation: Vec<usize>,
+}
+
+// quintic curve with zero first and second derivatives at 0 and 1
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+impl Noise {
+    pub fn new(rng: &Rng) -> Self {
+        let gradients = (0..POINT_COUNT).map(|_| loop {
+            let v = random_vec_in_unit_sphere(rng);
+            if !v.near_zero() {
+                break v.unit()
+            }
+        }).collect();
+        let features = (0..POINT_COUNT).map(|_| Vector3::new(rng.f64(), rng.f64(), rng.f64())).collect();
+        let mut permutation: Vec<usize> = (0..POINT_COUNT).collect();
+        rng.shuffle(&mut permutation);
+        permutation.extend_from_within(..);
+
+        Noise {gradients, features, permutation}
+    }
+
+    fn hash(&self, x: i64, y: i64, z: i64) -> usize {
+        let p = &self.permutation;
+        let mask = POINT_COUNT as i64 - 1;
+        p[p[p[(x & mask) as usize] + (y & mask) as usize] + (z & mask) as usize]
+    }
+
+    // gradient noise, about in [-1, 1] and 0 at integer coordinates
+    pub fn perlin(&self, p: Vector3) -> f64 {
+        let (x0, y0, z0) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (fx, fy, fz) = (p.x - x0, p.y - y0, p.z - z0);
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+
+        let mut sum = 0.0;
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let gradient = self.gradients[self.hash(x0 as i64 + i, y0 as i64 + j, z0 as i64 + k)];
+                    let offset = Vector3::new(fx - i as f64, fy - j as f64, fz - k as f64);
+                    let weight = match i { 0 => 1.0 - u, _ => u }
+                        * match j { 0 => 1.0 - v, _ => v }
+                        * match k { 0 => 1.0 - w, _ => w };
+                    sum += weight * gradient.dot(offset);
+                }
+            }
+        }
+        sum
+    }
+
+    // sum of the absolute noise over `octaves`, each one twice the frequency and half the weight
+    // of the previous one. Mostly in [0, 1]
+    pub fn turbulence(&self, p: Vector3, octaves: u32) -> f64 {
+        let mut sum = 0.0;
+        let (mut point, mut weight) = (p, 1.0);
+        for _ in 0..octaves {
+            sum += weight * self.perlin(point).abs();
+            point = point * 2.0;
+            weight *= 0.5;
+        }
+        sum
+    }
+
+    // fractal brownian motion: octaves of signed noise, each `lacunarity` times the frequency and
+    // `gain` times the weight of the previous one, normalized to about [-1, 1]
+    pub fn fbm(&self, p: Vector3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let (mut point, mut weight) = (p, 1.0);
+        for _ in 0..octaves {
+            sum += weight * self.perlin(point);
+            total += weight;
+            point = point * lacunarity;
+            weight *= gain;
+        }
+        match total > 0.0 {
+            true => sum / total,
+            false => 0.0,
+        }
+    }
+
+    // cellular noise: distance to the closest of the random points placed one per unit cell
+    pub fn worley(&self, p: Vector3) -> f64 {
+        let (x0, y0, z0) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+        let mut closest = f64::INFINITY;
+        for x in x0 - 1..=x0 + 1 {
+            for y in y0 - 1..=y0 + 1 {
+                for z in z0 - 1..=z0 + 1 {
+                    let feature = Vector3::new(x as f64, y as f64, z as f64) + self.features[self.hash(x, y, z)];
+                    closest = f64::min(closest, (feature - p).length_squared());
+                }
+            }
+        }
+        closest.sqrt()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Noise;
+    use crate::{utils::random_vec_in_unit_sphere, vector3::Vector3};
+
+    #[test]
+    fn noise_test() {
+        let rng = fastrand::Rng::with_seed(6);
+        let noise = Noise::new(&rng);
+        let same = Noise::new(&fastrand::Rng::with_seed(6));
+
+        assert_eq!(0.0, noise.perlin(Vector3::new(3.0, -2.0, 7.0)));
+        for _ in 0..1000 {
+            let p = random_vec_in_unit_sphere(&rng) * 20.0;
+            let n = noise.perlin(p);
+            assert!((-1.0..=1.0).contains(&n));
+            assert_eq!(n, same.perlin(p));
+            assert!((-1.0..=1.0).contains(&noise.fbm(p, 5, 2.0, 0.5)));
+            assert!((0.0..=2.0).contains(&noise.turbulence(p, 5)));
+            // one feature point per cell, so one is always within the cell's diagonal
+            assert!((0.0..=f64::sqrt(3.0)).contains(&noise.worley(p)));
+        }
+        // continuous across cells
+        let p = Vector3::new(1.0, 0.5, 0.5);
+        let e = Vector3::new(1e-9, 0.0, 0.0);
+        assert!((noise.perlin(p - e) - noise.perlin(p + e)).abs() < 1e-6);
+        assert!((noise.worley(p - e) - noise.worley(p + e)).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{color::RGBColor, hittable::{Shape, World}, material::Material, mesh::TriangleMesh, vector3::Vector3, utils::clamp};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io {file: String, error: std::io::Error},
+    Parse {file: String, line: usize, message: String},
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io {file, error} => write!(f, "{}: {}", file, error),
+            ObjError::Parse {file, line, message} => write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+// indices of a face corner into the model's buffers
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct ObjVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+// triangles sharing a group name and a material
+pub struct ObjGroup {
+    pub name: String,
+    pub material: Material,
+    pub triangles: Vec<[ObjVertex; 3]>,
+}
+
+pub struct ObjModel {
+    pub positions: Vec<Vector3>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub groups: Vec<ObjGroup>,
+}
+
+// materials of a `.mtl` file by name
+pub type MaterialLibrary = HashMap<String, Material>;
+
+// used for faces before any `usemtl`
+pub fn default_material() -> Material {
+    Material::Lambertian(RGBColor::new(0.8, 0.8, 0.8).into())
+}
+
+// splits a line into its keyword and the rest, ignoring comments
+fn tokenize(line: &str) -> Option<(&str, Vec<&str>)> {
+    let line = match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    let mut tokens = line.split_whitespace();
+    let keyword = tokens.next()?;
+    Some((keyword, tokens.collect()))
+}
+
+struct LineParser<'a> {
+    file: &'a str,
+    line: usize,
+}
+
+impl LineParser<'_> {
+    fn error(&self, message: String) -> ObjError {
+        ObjError::Parse {file: self.file.to_string(), line: self.line, message}
+    }
+    fn floats(&self, keyword: &str, args: &[&str], min: usize, max: usize) -> Result<Vec<f64>, ObjError> {
+        if args.len() < min || args.len() > max {
+            return Err(self.error(format!("`{}` expects {} to {} numbers, found {}", keyword, min, max, args.len())))
+        }
+        args.iter()
+            .map(|a| a.parse::<f64>().map_err(|_| self.error(format!("invalid number `{}` in `{}`", a, keyword))))
+            .collect()
+    }
+    fn color(&self, keyword: &str, args: &[&str]) -> Result<RGBColor, ObjError> {
+        let c = self.floats(keyword, args, 1, 3)?;
+        // a single value is a gray level
+        match c.len() {
+            1 => Ok(RGBColor::new(c[0], c[0], c[0])),
+            3 => Ok(RGBColor::new(c[0], c[1], c[2])),
+            _ => Err(self.error(format!("`{}` expects 1 or 3 numbers, found {}", keyword, c.len()))),
+        }
+    }
+    // resolves a 1-based (or negative, relative to the end) index into a buffer of `len` elements
+    fn index(&self, token: &str, len: usize, kind: &str) -> Result<usize, ObjError> {
+        let i: i64 = token.parse().map_err(|_| self.error(format!("invalid {} index `{}`", kind, token)))?;
+        let resolved = match i {
+            i if i > 0 => i - 1,
+            i if i < 0 => len as i64 + i,
+            _ => return Err(self.error(format!("{} index can't be 0", kind))),
+        };
+        if resolved < 0 || resolved >= len as i64 {
+            return Err(self.error(format!("{} index {} out of range, there are {} so far", kind, i, len)))
+        }
+        Ok(resolved as usize)
+    }
+}
+
+// material properties read from a `.mtl` file, before mapping them onto `Material`
+struct MtlEntry {
+    diffuse: RGBColor,
+    specular: RGBColor,
+    emission: RGBColor,
+    shininess: f64,
+    ior: f64,
+    dissolve: f64,
+    // color of light left after going through a unit of transparent materials
+    transmission: RGBColor,
+    illum: i32,
+    metallic: Option<f64>,
+    roughness: Option<f64>,
+}
+
+impl MtlEntry {
+    fn new() -> Self {
+        MtlEntry {
+            diffuse: RGBColor::new(0.8, 0.8, 0.8),
+            specular: RGBColor::new(0.0, 0.0, 0.0),
+            emission: RGBColor::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+            transmission: RGBColor::new(1.0, 1.0, 1.0),
+            illum: 2,
+            metallic: None,
+            roughness: None,
+        }
+    }
+    fn to_material(&self) -> Material {
+        let max = |c: RGBColor| f64::max(c.r(), f64::max(c.g(), c.b()));
+        // exporters write the roughness either directly (Pr) or as a phong exponent (Ns)
+        let roughness = self.roughness.unwrap_or(1.0 - f64::sqrt(clamp(self.shininess / 1000.0, 0.0, 1.0)));
+
+        if max(self.emission) > 0.0 {
+            return Material::DiffuseLight(self.emission, 1.0)
+        }
+        // transparent or one of the refraction illumination models
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let clamped = |c: f64| clamp(c, 1e-6, 1.0);
+            let transmission = RGBColor::new(clamped(self.transmission.r()), clamped(self.transmission.g()), clamped(self.transmission.b()));
+            return Material::Dielectric(self.ior.into(), Material::absorption(transmission, 1.0))
+        }
+        match self.metallic {
+            Some(m) if m >= 0.5 => Material::Metal(self.diffuse.into(), roughness),
+            Some(_) => Material::Lambertian(self.diffuse.into()),
+            None if max(self.specular) > max(self.diffuse) => Material::Metal(self.specular.into(), roughness),
+            None => Material::Lambertian(self.diffuse.into()),
+        }
+    }
+}
+
+pub fn parse_mtl(source: &str, file: &str) -> Result<MaterialLibrary, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlEntry)> = None;
+
+    for (i, line) in source.lines().enumerate() {
+        let p = LineParser {file, line: i + 1};
+        let (keyword, args) = match tokenize(line) {
+            Some(t) => t,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            if let Some((name, entry)) = current.take() {
+                materials.insert(name, entry.to_material());
+            }
+            if args.is_empty() {
+                return Err(p.error("`newmtl` without a name".to_string()))
+            }
+            current = Some((args.join(" "), MtlEntry::new()));
+            continue;
+        }
+        let entry = match &mut current {
+            Some((_, entry)) => entry,
+            None => return Err(p.error(format!("`{}` before any `newmtl`", keyword))),
+        };
+        match keyword {
+            "Kd" => entry.diffuse = p.color(keyword, &args)?,
+            "Ks" => entry.specular = p.color(keyword, &args)?,
+            "Ke" => entry.emission = p.color(keyword, &args)?,
+            "Ns" => entry.shininess = p.floats(keyword, &args, 1, 1)?[0],
+            "Ni" => entry.ior = p.floats(keyword, &args, 1, 1)?[0],
+            "d" => entry.dissolve = p.floats(keyword, &args, 1, 1)?[0],
+            "Tf" => entry.transmission = p.color(keyword, &args)?,
+            "Tr" => entry.dissolve = 1.0 - p.floats(keyword, &args, 1, 1)?[0],
+            "Pm" => entry.metallic = Some(p.floats(keyword, &args, 1, 1)?[0]),
+            "Pr" => entry.roughness = Some(p.floats(keyword, &args, 1, 1)?[0]),
+            "illum" => entry.illum = p.floats(keyword, &args, 1, 1)?[0] as i32,
+            // textures and the rest aren't supported yet
+            _ => continue,
+        }
+    }
+    if let Some((name, entry)) = current.take() {
+        materials.insert(name, entry.to_material());
+    }
+    Ok(materials)
+}
+
+// splits a polygon into triangles by ear clipping, in the plane the polygon faces the most.
+// Falls back to a fan when the polygon is degenerate
+fn triangulate(points: &[Vector3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]]
+    }
+    let fan = || (1..n - 1).map(|i| [0, i, i + 1]).collect();
+
+    // Newell's method for the polygon normal, then drop its largest axis
+    let mut normal = Vector3::new(0.0, 0.0, 0.0);
+    for i in 0..n {
+        let a = points[i];
+        let b = points[(i + 1) % n];
+        normal = normal + Vector3::new((a.y - b.y) * (a.z + b.z), (a.z - b.z) * (a.x + b.x), (a.x - b.x) * (a.y + b.y));
+    }
+    if normal.near_zero() {
+        return fan()
+    }
+    let drop = match (normal.x.abs(), normal.y.abs(), normal.z.abs()) {
+        (x, y, z) if x >= y && x >= z => 0,
+        (_, y, z) if y >= z => 1,
+        _ => 2,
+    };
+    let (ax, ay) = match drop {
+        0 => (1, 2),
+        1 => (2, 0),
+        _ => (0, 1),
+    };
+    let flat: Vec<(f64, f64)> = points.iter().map(|p| (p[ax], p[ay])).collect();
+    // makes the projected polygon counter-clockwise
+    let orientation = f64::signum(normal[drop]);
+    let cross = |o: usize, a: usize, b: usize| {
+        ((flat[a].0 - flat[o].0) * (flat[b].1 - flat[o].1) - (flat[a].1 - flat[o].1) * (flat[b].0 - flat[o].0)) * orientation
+    };
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (prev, cur, next) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            if cross(prev, cur, next) <= 0.0 {
+                return false
+            }
+            // no other vertex may lie inside the ear
+            remaining.iter().all(|&j| {
+                j == prev || j == cur || j == next
+                    || cross(prev, cur, j) < 0.0 || cross(cur, next, j) < 0.0 || cross(next, prev, j) < 0.0
+            })
+        });
+        match ear {
+            Some(i) => {
+                triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+                remaining.remove(i);
+            }
+            None => return fan(),
+        }
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+impl ObjModel {
+    // reads an `.obj` file and the material libraries it references, relative to its folder
+    pub fn load(path: &Path) -> Result<ObjModel, ObjError> {
+        let file = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|error| ObjError::Io {file: file.clone(), error})?;
+        let folder = path.parent().unwrap_or(Path::new(""));
+
+        ObjModel::parse(&source, &file, &mut |name| {
+            let mtl_path = folder.join(name);
+            let mtl_file = mtl_path.display().to_string();
+            let mtl_source = fs::read_to_string(&mtl_path).map_err(|error| ObjError::Io {file: mtl_file.clone(), error})?;
+            parse_mtl(&mtl_source, &mtl_file)
+        })
+    }
+
+    // `load_mtl` is called with the name of every `mtllib` and returns its materials
+    pub fn parse(source: &str, file: &str,
+                 load_mtl: &mut dyn FnMut(&str) -> Result<MaterialLibrary, ObjError>) -> Result<ObjModel, ObjError> {
+        let mut model = ObjModel {positions: Vec::new(), normals: Vec::new(), uvs: Vec::new(), groups: Vec::new()};
+        let mut materials = MaterialLibrary::new();
+        let mut group_name = String::from("default");
+        let mut material = default_material();
+        let mut triangles: Vec<[ObjVertex; 3]> = Vec::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let p = LineParser {file, line: i + 1};
+            let (keyword, args) = match tokenize(line) {
+                Some(t) => t,
+                None => continue,
+            };
+            match keyword {
+                "v" => {
+                    // the optional fourth component is a rational weight, unused here
+                    let v = p.floats(keyword, &args, 3, 4)?;
+                    model.positions.push(Vector3::new(v[0], v[1], v[2]));
+                }
+                "vn" => {
+                    let n = p.floats(keyword, &args, 3, 3)?;
+                    model.normals.push(Vector3::new(n[0], n[1], n[2]));
+                }
+                "vt" => {
+                    let t = p.floats(keyword, &args, 1, 3)?;
+                    model.uvs.push((t[0], t.get(1).copied().unwrap_or(0.0)));
+                }
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(p.error(format!("a face needs at least 3 vertices, found {}", args.len())))
+                    }
+                    let mut corners = Vec::with_capacity(args.len());
+                    for arg in &args {
+                        // v, v/vt, v//vn or v/vt/vn
+                        let mut parts = arg.split('/');
+                        let position = p.index(parts.next().unwrap_or(""), model.positions.len(), "vertex")?;
+                        let uv = match parts.next() {
+                            Some(t) if !t.is_empty() => Some(p.index(t, model.uvs.len(), "texture coordinate")?),
+                            _ => None,
+                        };
+                        let normal = match parts.next() {
+                            Some(t) if !t.is_empty() => Some(p.index(t, model.normals.len(), "normal")?),
+                            _ => None,
+                        };
+                        if parts.next().is_some() {
+                            return Err(p.error(format!("invalid face vertex `{}`", arg)))
+                        }
+                        corners.push(ObjVertex {position, uv, normal});
+                    }
+                    let points: Vec<Vector3> = corners.iter().map(|c| model.positions[c.position]).collect();
+                    for [a, b, c] in triangulate(&points) {
+                        triangles.push([corners[a], corners[b], corners[c]]);
+                    }
+                }
+                "g" | "o" | "usemtl" => {
+                    if !triangles.is_empty() {
+                        model.groups.push(ObjGroup {name: group_name.clone(), material: material.clone(), triangles: std::mem::take(&mut triangles)});
+                    }
+                    if keyword == "usemtl" {
+                        let name = args.join(" ");
+                        material = match materials.get(&name) {
+                            Some(m) => m.clone(),
+                            None => return Err(p.error(format!("unknown material `{}`", name))),
+                        };
+                    } else if !args.is_empty() {
+                        group_name = args.join(" ");
+                    }
+                }
+                "mtllib" => {
+                    for name in &args {
+                        materials.extend(load_mtl(name)?);
+                    }
+                }
+                // smoothing groups, lines, points, curves and display or render attributes like
+                // `usemap`, `lod` or `shadow_obj` aren't used
+                _ => continue,
+            }
+        }
+        if !triangles.is_empty() {
+            model.groups.push(ObjGroup {name: group_name, material, triangles});
+        }
+        Ok(model)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.groups.iter().map(|g| g.triangles.len()).sum()
+    }
+
+    // one mesh per group, with the corners sharing the same position, uv and normal merged
+    // into a single vertex. Groups where some corners have no normal are shaded flat, and
+    // groups where some have no uv use barycentric coordinates
+    pub fn to_meshes(&self) -> Vec<TriangleMesh> {
+        self.groups.iter().map(|group| {
+            let corners = || group.triangles.iter().flatten();
+            let smooth = corners().all(|c| c.normal.is_some());
+            let textured = corners().all(|c| c.uv.is_some());
+
+            let mut vertices: HashMap<(usize, Option<usize>, Option<usize>), u32> = HashMap::new();
+            let mut positions = Vec::new();
+            let mut normals = Vec::new();
+            let mut uvs = Vec::new();
+            let indices = group.triangles.iter().map(|triangle| triangle.map(|c| {
+                let key = (c.position, c.uv.filter(|_| textured), c.normal.filter(|_| smooth));
+                *vertices.entry(key).or_insert_with(|| {
+                    positions.push(self.positions[c.position]);
+                    if let Some(n) = key.2 {
+                        normals.push(self.normals[n]);
+                    }
+                    if let Some(t) = key.1 {
+                        uvs.push(self.uvs[t]);
+                    }
+                    (positions.len() - 1) as u32
+                })
+            })).collect();
+
+            TriangleMesh::new(positions, normals, uvs, indices, group.material.clone())
+        }).collect()
+    }
+
+    pub fn add_to_world(&self, world: &mut World) {
+        for mesh in self.to_meshes() {
+            world.add(Shape::Mesh(Arc::new(mesh)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{parse_mtl, triangulate, ObjError, ObjModel};
+    use crate::{material::Material, spectrum::Ior, vector3::Vector3};
+
+    const CUBE_FACE: &str = "
+mtllib scene.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+g front # a comment
+usemtl red
+s 1
+lod 1
+usemap off
+f 1/1/1 2/2/1 3/3/1 4/4/1
+g back
+usemtl glass
+f -1//1 -2//1 -3//1
+";
+    const MTL: &str = "
+newmtl red
+Kd 0.8 0.1 0.1
+newmtl glass
+Kd 1 1 1
+Ni 1.45
+Tf 1 0.5 1
+d 0.1
+newmtl gold
+Kd 0.1 0.1 0.1
+Ks 1.0 0.8 0.3
+Ns 250
+newmtl lamp
+Ke 10 10 8
+";
+
+    #[test]
+    fn parse_test() {
+        let model = ObjModel::parse(CUBE_FACE, "cube.obj", &mut |name| {
+            assert_eq!("scene.mtl", name);
+            parse_mtl(MTL, name)
+        }).unwrap();
+
+        assert_eq!((4, 4, 1), (model.positions.len(), model.uvs.len(), model.normals.len()));
+        assert_eq!(2, model.groups.len());
+        assert_eq!(("front", 2), (model.groups[0].name.as_str(), model.groups[0].triangles.len()));
+        assert_eq!(("back", 1), (model.groups[1].name.as_str(), model.groups[1].triangles.len()));
+        assert!(model.groups[0].triangles.iter().flatten().all(|corner| corner.uv == Some(corner.position)));
+        assert_eq!((3, None, Some(0)), (model.groups[1].triangles[0][0].position, model.groups[1].triangles[0][0].uv, model.groups[1].triangles[0][0].normal));
+        assert!(matches!(model.groups[1].material, Material::Dielectric(Ior::Constant(ior), absorption)
+                         if ior == 1.45 && absorption.r() == 0.0 && (absorption.g() - f64::ln(2.0)).abs() < 1e-12));
+
+        let meshes = model.to_meshes();
+        assert_eq!((2, 4), (meshes[0].triangle_count(), meshes[0].vertex_count()));
+        assert_eq!((1, 3), (meshes[1].triangle_count(), meshes[1].vertex_count()));
+    }
+    #[test]
+    fn mtl_test() {
+        let materials = parse_mtl(MTL, "scene.mtl").unwrap();
+
+        assert!(matches!(materials["red"], Material::Lambertian(_)));
+        assert!(matches!(materials["gold"], Material::Metal(_, fuzz) if (fuzz - 0.5).abs() < 1e-12));
+        assert!(matches!(materials["lamp"], Material::DiffuseLight(_, _)));
+    }
+    #[test]
+    fn error_line_test() {
+        let source = "v 0 0 0\nv 1 0 0\n\nf 1 2 3\n";
+        let err = ObjModel::parse(source, "bad.obj", &mut |_| Ok(HashMap::new())).err().unwrap();
+
+        assert!(matches!(err, ObjError::Parse {line: 4, ..}));
+        assert_eq!("bad.obj:4: vertex index 3 out of range, there are 2 so far", err.to_string());
+
+        let err = ObjModel::parse("v 0 0 zero\n", "bad.obj", &mut |_| Ok(HashMap::new())).err().unwrap();
+        assert_eq!("bad.obj:1: invalid number `zero` in `v`", err.to_string());
+    }
+    #[test]
+    fn triangulate_test() {
+        // concave arrow head, a fan from the first vertex would cover the notch
+        let points = [
+            Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 0.0), Vector3::new(4.0, 0.0, 0.0), Vector3::new(2.0, 3.0, 0.0),
+        ];
+        let triangles = triangulate(&points);
+
+        assert_eq!(2, triangles.len());
+        // the triangles exactly cover the polygon's area of 4
+        let area: f64 = triangles.iter()
+            .map(|[a, b, c]| (points[*b] - points[*a]).cross(points[*c] - points[*a]).length() / 2.0)
+            .sum();
+        assert!((area - 4.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,267 @@
+use std::f64::consts::PI;
+
+use fastrand::Rng;
+
+use crate::{color::RGBColor, hittable::HitRecord, material::{LightReaction, ScatterRecord}, microfacet::{fresnel_dielectric, refract, Ggx},
+            ray::Ray, texture::Texture, utils::random_vec_in_unit_sphere, vector3::Vector3};
+
+// one material for everything from plastic to metal and glass, after Disney's principled bsdf.
+// Besides the base color, parameters are textures read by their luminance, from 0 to 1
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Texture,
+    // conductor reflecting the base color, rather than dielectric
+    pub metallic: Texture,
+    pub roughness: Texture,
+    // strength of the reflection off dielectrics, 0.5 being the 4% of common materials
+    pub specular: Texture,
+    // soft white reflection at grazing angles, like off cloth
+    pub sheen: Texture,
+    // second, white reflection off a coat of varnish
+    pub clearcoat: Texture,
+    pub clearcoat_roughness: Texture,
+    // glass rather than opaque, tinted by the base color
+    pub transmission: Texture,
+    // flattens the diffuse reflection like light scattered under the surface
+    pub subsurface: Texture,
+    // index of refraction of transmission
+    pub ior: f64,
+}
+
+// parameters read at a hit
+struct Parameters {
+    base_color: RGBColor,
+    metallic: f64,
+    roughness: f64,
+    specular: f64,
+    sheen: f64,
+    clearcoat: f64,
+    clearcoat_roughness: f64,
+    transmission: f64,
+    subsurface: f64,
+}
+
+impl Parameters {
+    // reflectance of the specular lobe head on, from the specular strength for dielectrics
+    // to the base color for metals
+    fn specular_color(&self) -> RGBColor {
+        let dielectric = 0.08 * self.specular;
+        RGBColor::new(dielectric, dielectric, dielectric) * (1.0 - self.metallic) + self.base_color * self.metallic
+    }
+    // weights of the diffuse, specular, glass and clearcoat lobes in the bsdf, and the
+    // probabilities of sampling them
+    fn weights(&self) -> ([f64; 4], [f64; 4]) {
+        let dielectric = 1.0 - self.metallic;
+        let weights = [dielectric * (1.0 - self.transmission), 1.0 - dielectric * self.transmission,
+                       dielectric * self.transmission, 0.25 * self.clearcoat];
+        // the specular lobe is faint on dielectrics
+        let mut probabilities = [weights[0], weights[1] * (0.5 + 0.5 * self.metallic), weights[2], weights[3]];
+        let sum: f64 = probabilities.iter().sum();
+        for p in &mut probabilities {
+            *p /= sum;
+        }
+        (weights, probabilities)
+    }
+}
+
+// Schlick's approximation of the fresnel term
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+impl Principled {
+    pub fn new(base_color: Texture) -> Self {
+        let value = |v: f64| Texture::Solid(RGBColor::new(v, v, v));
+        Principled {
+            base_color,
+            metallic: value(0.0),
+            roughness: value(0.5),
+            specular: value(0.5),
+            sheen: value(0.0),
+            clearcoat: value(0.0),
+            clearcoat_roughness: value(0.03),
+            transmission: value(0.0),
+            subsurface: value(0.0),
+            ior: 1.5,
+        }
+    }
+
+    fn parameters(&self, rec: &HitRecord) -> Parameters {
+        let value = |texture: &Texture| texture.value(rec.u, rec.v, rec.point).luminance().clamp(0.0, 1.0);
+        Parameters {
+            base_color: self.base_color.value(rec.u, rec.v, rec.point),
+            metallic: value(&self.metallic),
+            roughness: value(&self.roughness),
+            specular: value(&self.specular),
+            sheen: value(&self.sheen),
+            clearcoat: value(&self.clearcoat),
+            clearcoat_roughness: value(&self.clearcoat_roughness),
+            transmission: value(&self.transmission),
+            subsurface: value(&self.subsurface),
+        }
+    }
+
+    // index of the other side of the surface over the index of the side of the ray
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        match rec.front_face {
+            true => self.ior,
+            false => 1.0 / self.ior,
+        }
+    }
+
+    // bsdf times cosine, and density of sampling `wi`, for light leaving along `wo`
+    fn evaluate(&self, params: &Parameters, rec: &HitRecord, wo: Vector3, wi: Vector3) -> (RGBColor, f64) {
+        let black = RGBColor::new(0.0, 0.0, 0.0);
+        let normal = rec.normal;
+        let (cos_o, cos_i) = (wo.dot(normal), wi.dot(normal));
+        if cos_o <= 0.0 || cos_i == 0.0 {
+            return (black, 0.0)
+        }
+        let (weights, probabilities) = params.weights();
+        let (mut value, mut pdf) = (black, 0.0);
+
+        if cos_i > 0.0 {
+            // diffuse, with Burley's retroreflection at grazing angles or flattened by subsurface
+            // scattering, and the sheen
+            let cos_d = wi.dot((wo + wi).unit());
+            let (fl, fv) = (schlick_weight(cos_i), schlick_weight(cos_o));
+            let fd90 = 0.5 + 2.0 * params.roughness * cos_d * cos_d;
+            let fd = lerp(1.0, fd90, fl) * lerp(1.0, fd90, fv);
+            let fss90 = params.roughness * cos_d * cos_d;
+            let fss = lerp(1.0, fss90, fl) * lerp(1.0, fss90, fv);
+            let ss = 1.25 * (fss * (1.0 / (cos_i + cos_o) - 0.5) + 0.5);
+            let sheen = params.sheen * schlick_weight(cos_d);
+            let diffuse = params.base_color * (lerp(fd, ss, params.subsurface) / PI) + RGBColor::new(sheen, sheen, sheen);
+            value = value + diffuse * (weights[0] * cos_i);
+            pdf += probabilities[0] * cos_i / PI;
+
+            // the specular reflection and the clearcoat
+            if let Some((v, p, cos)) = Ggx::new(params.roughness).reflection(normal, wo, wi) {
+                let w = schlick_weight(cos);
+                let fresnel = params.specular_color() * (1.0 - w) + RGBColor::new(w, w, w);
+                value = value + fresnel * (weights[1] * v);
+                pdf += probabilities[1] * p;
+            }
+            if let Some((v, p, cos)) = Ggx::new(params.clearcoat_roughness).reflection(normal, wo, wi) {
+                let fresnel = lerp(0.04, 1.0, schlick_weight(cos));
+                value = value + RGBColor::new(1.0, 1.0, 1.0) * (weights[3] * fresnel * v);
+                pdf += probabilities[3] * p;
+            }
+        }
+        if weights[2] > 0.0 {
+            let (v, p) = Ggx::new(params.roughness).dielectric(self.eta(rec), normal, wo, wi);
+            value = value + params.base_color * (weights[2] * v);
+            pdf += probabilities[2] * p;
+        }
+        (value, pdf)
+    }
+}
+
+impl LightReaction for Principled {
+    fn sample(&self, rng: &Rng, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let params = self.parameters(rec);
+        let wo = -r_in.direction.unit();
+        if wo.dot(rec.normal) <= 0.0 {
+            return None
+        }
+
+        // a direction from one lobe, weighted by the density of all of them
+        let (_, probabilities) = params.weights();
+        let mut pick = rng.f64();
+        let lobe = probabilities.iter().position(|p| {
+            pick -= p;
+            pick < 0.0
+        }).unwrap_or(1);
+        let direction = match lobe {
+            0 => {
+                let direction = rec.normal + random_vec_in_unit_sphere(rng).unit();
+                match direction.near_zero() {
+                    true => rec.normal,
+                    false => direction.unit(),
+                }
+            }
+            1 | 3 => {
+                let roughness = if lobe == 1 { params.roughness } else { params.clearcoat_roughness };
+                (-wo).reflect(Ggx::new(roughness).sample_visible(rng, rec.normal, wo))
+            }
+            _ => {
+                let m = Ggx::new(params.roughness).sample_visible(rng, rec.normal, wo);
+                let eta = self.eta(rec);
+                match rng.f64() < fresnel_dielectric(wo.dot(m), eta) {
+                    true => (-wo).reflect(m),
+                    false => refract(wo, m, eta)?,
+                }
+            }
+        };
+
+        let (value, pdf) = self.evaluate(&params, rec, wo, direction.unit());
+        if pdf <= 0.0 {
+            return None
+        }
+        Some(ScatterRecord {ray: Ray::with_time(rec.point, direction, r_in.time), attenuation: value * (1.0 / pdf), pdf: Some(pdf)})
+    }
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> RGBColor {
+        self.evaluate(&self.parameters(rec), rec, -r_in.direction.unit(), direction.unit()).0
+    }
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vector3) -> f64 {
+        self.evaluate(&self.parameters(rec), rec, -r_in.direction.unit(), direction.unit()).1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Principled;
+    use crate::{color::RGBColor, hittable::HitRecord, material::LightReaction, ray::Ray, texture::Texture, vector3::Vector3};
+
+    #[test]
+    fn principled_test() {
+        let rng = fastrand::Rng::with_seed(21);
+        let value = |v: f64| Texture::Solid(RGBColor::new(v, v, v));
+        let mut rec = HitRecord::new();
+        rec.normal = Vector3::new(0.0, 1.0, 0.0);
+        rec.front_face = true;
+        let r_in = Ray::new(Vector3::new(0.5, 1.0, 0.0), Vector3::new(-0.5, -1.0, 0.2));
+
+        let plastic = Principled::new(RGBColor::new(0.8, 0.2, 0.2).into());
+        let mut metal = Principled::new(RGBColor::new(0.9, 0.7, 0.3).into());
+        metal.metallic = value(1.0);
+        metal.roughness = value(0.3);
+        let mut glass = Principled::new(RGBColor::new(1.0, 1.0, 1.0).into());
+        glass.transmission = value(1.0);
+        glass.roughness = value(0.1);
+        let mut varnished = Principled::new(RGBColor::new(0.2, 0.5, 0.2).into());
+        varnished.clearcoat = value(1.0);
+        varnished.sheen = value(1.0);
+        varnished.subsurface = value(1.0);
+
+        for material in [&plastic, &metal, &glass, &varnished] {
+            let n = 4000;
+            let (mut reflected, mut transmitted) = (RGBColor::new(0.0, 0.0, 0.0), 0.0);
+            for _ in 0..n {
+                let scattered = match material.sample(&rng, &r_in, &rec) {
+                    Some(scattered) => scattered,
+                    None => continue,
+                };
+                let direction = scattered.ray.direction;
+                let pdf = material.pdf(&r_in, &rec, direction);
+                assert!((scattered.pdf.unwrap() - pdf).abs() < 1e-6 * pdf);
+                let weight = material.eval(&r_in, &rec, direction) * (1.0 / pdf);
+                assert!((weight.r() - scattered.attenuation.r()).abs() < 1e-6);
+                match direction.y > 0.0 {
+                    true => reflected = reflected + scattered.attenuation * (1.0 / n as f64),
+                    false => transmitted += scattered.attenuation.g() / n as f64,
+                }
+            }
+            // the surfaces keep about as much light as their colors
+            assert!(reflected.r() + transmitted < 1.05, "{:?} {}", reflected, transmitted);
+            match material.transmission.value(0.0, 0.0, Vector3::new(0.0, 0.0, 0.0)).g() {
+                0.0 => assert_eq!(0.0, transmitted),
+                _ => assert!(transmitted > 0.8, "{}", transmitted),
+            }
+        }
+    }
+}
@@ -1,17 +1,35 @@
-use crate::vector3::Vector3;
+use crate::{color::RGBColor, spectrum::Wavelengths, vector3::Vector3};
 
 
 
 pub struct Ray {
     pub origin: Vector3,
     pub direction: Vector3,
+    // instant in the camera's shutter interval the ray is traced at, for moving shapes
+    pub time: f64,
+    // the wavelengths carried by the ray when rendering spectrally
+    pub wavelengths: Option<Wavelengths>,
+    // absorption per unit length of the glass the ray goes through, if any
+    pub absorption: Option<RGBColor>,
 }
 
 impl Ray {
     pub fn new(origin: Vector3, direction: Vector3) -> Self {
         Ray {
             origin,
-            direction
+            direction,
+            time: 0.0,
+            wavelengths: None,
+            absorption: None,
+        }
+    }
+    pub fn with_time(origin: Vector3, direction: Vector3, time: f64) -> Self {
+        Ray {
+            origin,
+            direction,
+            time,
+            wavelengths: None,
+            absorption: None,
         }
     }
     pub fn at(&self,t: f64) -> Vector3 {
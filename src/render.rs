@@ -0,0 +1,338 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use fastrand::Rng;
+
+use crate::{camera::Camera, color::RGBColor, framebuffer::Framebuffer, hittable::{Hittable, World}, spectrum::Wavelengths, utils::ray_color, vector3::Vector3};
+
+pub const TILE_SIZE: usize = 16;
+
+// names of the output variable channels in the framebuffer
+pub const DEPTH_AOV: &str = "Z";
+pub const NORMAL_AOVS: [&str; 3] = ["N.X", "N.Y", "N.Z"];
+
+// extra channels recorded from the first hit of the camera rays, averaged over the samples
+#[derive(Debug,PartialEq,Clone,Copy,Default)]
+pub struct Aovs {
+    // distance from the camera, infinite where nothing was hit
+    pub depth: bool,
+    // world space normal facing the camera, zero where nothing was hit
+    pub normal: bool,
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub seed: u64,
+    pub threads: usize,
+    pub aovs: Aovs,
+    // traces wavelengths of light rather than rgb colors, for dispersion
+    pub spectral: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings::with_aspect_ratio(400, 3.0/2.0)
+    }
+}
+
+impl RenderSettings {
+    pub fn with_aspect_ratio(width: usize, aspect_ratio: f64) -> Self {
+        RenderSettings {
+            width,
+            height: ((width as f64)/aspect_ratio) as usize,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            seed: 10,
+            threads: default_thread_count(),
+            aovs: Aovs::default(),
+            spectral: false,
+        }
+    }
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+}
+
+// rectangular block of pixels, in image coordinates (row 0 is the top of the image)
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct Tile {
+    pub index: usize,
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+pub fn tiles(width: usize, height: usize) -> Vec<Tile> {
+    let mut list = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE) {
+        for x in (0..width).step_by(TILE_SIZE) {
+            list.push(Tile {
+                index: list.len(),
+                x,
+                y,
+                width: usize::min(TILE_SIZE, width - x),
+                height: usize::min(TILE_SIZE, height - y),
+            });
+        }
+    }
+    list
+}
+
+// every tile gets its own random stream derived from the render seed and the tile index,
+// so the image doesn't depend on which thread renders which tile (splitmix64 finalizer)
+pub fn tile_seed(seed: u64, tile_index: usize) -> u64 {
+    let mut z = seed.wrapping_add((tile_index as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+pub fn default_thread_count() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// output of a single tile, the output variables are empty when disabled
+struct TilePixels {
+    colors: Vec<RGBColor>,
+    depth: Vec<f32>,
+    normals: Vec<Vector3>,
+}
+
+pub struct Renderer {
+    pub settings: RenderSettings,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Self {
+        Renderer { settings }
+    }
+
+    fn render_tile(&self, tile: &Tile, world: &World, cam: &Camera) -> TilePixels {
+        let RenderSettings {width, height, samples_per_pixel, max_depth, seed, aovs, spectral, ..} = self.settings;
+        let rng = Rng::with_seed(tile_seed(seed, tile.index));
+        let mut pixels = TilePixels {
+            colors: Vec::with_capacity(tile.width * tile.height),
+            depth: Vec::new(),
+            normals: Vec::new(),
+        };
+
+        for y in tile.y..tile.y + tile.height {
+            // the camera's t coordinate grows upwards
+            let h = height - 1 - y;
+            for w in tile.x..tile.x + tile.width {
+                let mut pixel_color = RGBColor::new(0.0, 0.0, 0.0);
+                let mut depth = 0.0;
+                let mut normal = Vector3::new(0.0, 0.0, 0.0);
+                let mut hits = 0;
+                for _s in 0..samples_per_pixel {
+                    let v = (h as f64 + rng.f64()) / height as f64;
+                    let u = (w as f64 + rng.f64()) / width as f64;
+
+                    let mut ray = cam.get_ray(u, v, &rng);
+                    if aovs.depth || aovs.normal {
+                        if let Some(rec) = world.hit(&ray, 0.001, f64::INFINITY) {
+                            depth += rec.t * ray.direction.length();
+                            normal = normal + rec.normal;
+                            hits += 1;
+                        }
+                    }
+                    pixel_color = pixel_color + match spectral {
+                        true => {
+                            let wavelengths = Wavelengths::sample(rng.f64());
+                            ray.wavelengths = Some(wavelengths);
+                            wavelengths.to_rgb(ray_color(&ray, world, max_depth, &rng))
+                        }
+                        false => ray_color(&ray, world, max_depth, &rng),
+                    };
+                }
+                pixels.colors.push(pixel_color * (1.0 / samples_per_pixel as f64));
+
+                if aovs.depth {
+                    pixels.depth.push(match hits {
+                        0 => f32::INFINITY,
+                        _ => (depth / hits as f64) as f32,
+                    });
+                }
+                if aovs.normal {
+                    pixels.normals.push(match normal.near_zero() {
+                        true => normal,
+                        false => normal.unit(),
+                    });
+                }
+            }
+        }
+        pixels
+    }
+
+    // renders the image on `settings.threads` threads. The result only depends on
+    // `settings.seed`, not on the thread count
+    pub fn render(&self, world: &World, cam: &Camera) -> Framebuffer {
+        let tile_list = tiles(self.settings.width, self.settings.height);
+        let next_tile = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+
+        thread::scope(|s| {
+            for _ in 0..usize::max(self.settings.threads, 1) {
+                let sender = sender.clone();
+                let tile_list = &tile_list;
+                let next_tile = &next_tile;
+                s.spawn(move || {
+                    // threads pull tiles until there are none left
+                    loop {
+                        let i = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if i >= tile_list.len() {
+                            break;
+                        }
+                        let pixels = self.render_tile(&tile_list[i], world, cam);
+                        sender.send((i, pixels)).expect("Failed to send tile");
+                    }
+                });
+            }
+        });
+        drop(sender);
+
+        let mut image = Framebuffer::new(self.settings.width, self.settings.height);
+        if self.settings.aovs.depth {
+            image.add_aov(DEPTH_AOV);
+        }
+        if self.settings.aovs.normal {
+            for name in NORMAL_AOVS {
+                image.add_aov(name);
+            }
+        }
+        for (i, pixels) in receiver {
+            let tile = &tile_list[i];
+            for (j, color) in pixels.colors.into_iter().enumerate() {
+                image.set(tile.x + j % tile.width, tile.y + j / tile.width, color);
+            }
+            for (j, depth) in pixels.depth.into_iter().enumerate() {
+                image.set_aov(DEPTH_AOV, tile.x + j % tile.width, tile.y + j / tile.width, depth);
+            }
+            for (j, normal) in pixels.normals.into_iter().enumerate() {
+                for (axis, name) in NORMAL_AOVS.iter().enumerate() {
+                    image.set_aov(name, tile.x + j % tile.width, tile.y + j / tile.width, normal[axis] as f32);
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{tiles, Aovs, RenderSettings, Renderer, DEPTH_AOV, NORMAL_AOVS};
+    use crate::{background::Background, camera::Camera, color::RGBColor, hittable::{Shape, World}, material::Material, utils::random_scene, vector3::Vector3};
+
+    #[test]
+    fn tiles_test() {
+        let list = tiles(40, 20);
+
+        assert_eq!(6, list.len());
+        assert_eq!((32, 16, 8, 4), (list[5].x, list[5].y, list[5].width, list[5].height));
+        assert_eq!(40 * 20, list.iter().map(|t| t.width * t.height).sum::<usize>());
+    }
+    #[test]
+    fn thread_count_independent_test() {
+        let mut world = random_scene(&fastrand::Rng::with_seed(1));
+        world.build_bvh();
+        let cam = Camera::new(Vector3::new(8.0, 5.0, 10.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+                              20.0, 1.5, 0.1, 10.0);
+        let settings = RenderSettings {
+            width: 36,
+            height: 24,
+            samples_per_pixel: 2,
+            max_depth: 5,
+            seed: 7,
+            threads: 1,
+            aovs: Aovs { depth: true, normal: true },
+            spectral: false,
+        };
+
+        let single = Renderer::new(settings).render(&world, &cam);
+        let multi = Renderer::new(RenderSettings { threads: 4, ..settings }).render(&world, &cam);
+        assert_eq!(single, multi);
+    }
+    #[test]
+    fn single_row_test() {
+        // a one pixel tall image still maps its pixels onto the camera's view
+        let mut world = World::new();
+        world.add(Shape::Sphere {
+            center: Vector3::new(0.0, 0.0, 0.0),
+            radius: 0.7,
+            material: Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into()),
+        });
+        let cam = Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+                              10.0, 3.0, 0.0, 5.0);
+        // a single bounce leaves the sphere black against the sky
+        let settings = RenderSettings {
+            width: 3,
+            height: 1,
+            samples_per_pixel: 16,
+            max_depth: 1,
+            seed: 7,
+            threads: 1,
+            aovs: Aovs::default(),
+            spectral: false,
+        };
+        let image = Renderer::new(settings).render(&world, &cam);
+
+        let black = RGBColor::new(0.0, 0.0, 0.0);
+        assert_eq!(black, image.get(1, 0));
+        assert!(image.get(0, 0) != black && image.get(2, 0) != black);
+    }
+    #[test]
+    fn aovs_test() {
+        let mut world = World::new();
+        world.add(Shape::Sphere {
+            center: Vector3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into()),
+        });
+        // narrow view straight at the front of the sphere
+        let cam = Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+                              1.0, 1.0, 0.0, 5.0);
+        let settings = RenderSettings {
+            width: 8,
+            height: 8,
+            samples_per_pixel: 1,
+            max_depth: 1,
+            seed: 7,
+            threads: 1,
+            aovs: Aovs { depth: true, normal: true },
+            spectral: false,
+        };
+        let image = Renderer::new(settings).render(&world, &cam);
+
+        assert!(image.aov(DEPTH_AOV).unwrap().iter().all(|&d| (d - 4.0).abs() < 0.01));
+        assert!(image.aov(NORMAL_AOVS[2]).unwrap().iter().all(|&n| n > 0.99));
+    }
+    #[test]
+    fn spectral_test() {
+        // colors come back from the wavelengths they're traced at
+        let mut world = World::new();
+        world.background = Background::Solid(RGBColor::new(0.6, 0.3, 0.1));
+        let cam = Camera::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0),
+                              20.0, 1.0, 0.0, 5.0);
+        let settings = RenderSettings {
+            width: 4,
+            height: 4,
+            samples_per_pixel: 500,
+            max_depth: 2,
+            seed: 3,
+            threads: 1,
+            aovs: Aovs::default(),
+            spectral: true,
+        };
+        let image = Renderer::new(settings).render(&world, &cam);
+        let mean = image.pixels().iter().fold(RGBColor::new(0.0, 0.0, 0.0), |sum, p| sum + *p * (1.0 / 16.0));
+        for (a, b) in [(mean.r(), 0.6), (mean.g(), 0.3), (mean.b(), 0.1)] {
+            assert!((a - b).abs() < 0.02, "{:?}", mean);
+        }
+    }
+}
@@ -0,0 +1,1000 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fastrand::Rng;
+use toml::{Table, Value};
+
+use crate::{aabb::Aabb, background::{Background, EnvironmentMap, Sky}, camera::Camera, color::RGBColor, grid::{Emission, GridVolume, VoxelGrid}, hittable::{Shape, World},
+            image_reader::load_image, instance::{Instance, Motion}, material::Material, medium::{Medium, PhaseFunction, Volume}, microfacet::ComplexIor, mesh::TriangleMesh, obj::ObjModel, principled::Principled,
+            render::RenderSettings, spectrum::Ior, texture::{Filter, ImageTexture, NoisePattern, NoiseTexture, Texture, WrapMode},
+            transform::{AnimatedTransform, Transform}, vector3::Vector3};
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {file: String, error: std::io::Error},
+    // not valid toml, the message says where
+    Parse {file: String, message: String},
+    // valid toml that doesn't describe a scene, `key` is the dotted path of the offending value
+    Invalid {file: String, key: String, message: String},
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io {file, error} => write!(f, "{}: {}", file, error),
+            SceneError::Parse {file, message} => write!(f, "{}: {}", file, message),
+            SceneError::Invalid {file, key, message} => write!(f, "{}: {}: {}", file, key, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+// everything needed to render an image
+pub struct Scene {
+    pub world: World,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+}
+
+impl Scene {
+    // reads a `.toml` scene, with the files it references relative to its folder
+    pub fn load(path: &Path) -> Result<Scene, SceneError> {
+        let file = path.display().to_string();
+        let source = fs::read_to_string(path).map_err(|error| SceneError::Io {file: file.clone(), error})?;
+        Scene::parse(&source, &file, path.parent().unwrap_or(Path::new("")))
+    }
+
+    pub fn parse(source: &str, file: &str, folder: &Path) -> Result<Scene, SceneError> {
+        let root: Table = source.parse().map_err(|e: toml::de::Error| SceneError::Parse {file: file.to_string(), message: e.to_string()})?;
+        let mut loader = Loader {
+            file,
+            folder,
+            root: &root,
+            rng: Rng::with_seed(0),
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            resolving: HashSet::new(),
+            meshes: HashMap::new(),
+        };
+        loader.scene()
+    }
+}
+
+// joins a key to the path of the table it's in
+fn join(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) => "a string",
+        Value::Integer(_) => "an integer",
+        Value::Float(_) => "a float",
+        Value::Boolean(_) => "a boolean",
+        Value::Datetime(_) => "a date",
+        Value::Array(_) => "an array",
+        Value::Table(_) => "a table",
+    }
+}
+
+struct Loader<'a> {
+    file: &'a str,
+    folder: &'a Path,
+    root: &'a Table,
+    // seeds the noise textures that don't have a seed of their own
+    rng: Rng,
+    // named textures and materials already built
+    textures: HashMap<String, Texture>,
+    materials: HashMap<String, Material>,
+    // paths of the named textures and materials being built, to catch references in a loop
+    resolving: HashSet<String>,
+    // meshes of the obj files loaded with their own materials, shared by every shape using them
+    meshes: HashMap<PathBuf, Vec<Arc<TriangleMesh>>>,
+}
+
+impl Loader<'_> {
+    fn error(&self, key: &str, message: String) -> SceneError {
+        SceneError::Invalid {file: self.file.to_string(), key: key.to_string(), message}
+    }
+
+    fn check_keys(&self, table: &Table, path: &str, allowed: &[&str]) -> Result<(), SceneError> {
+        match table.keys().find(|k| !allowed.contains(&k.as_str())) {
+            Some(key) => Err(self.error(&join(path, key), format!("unknown key, expected one of {}", allowed.join(", ")))),
+            None => Ok(()),
+        }
+    }
+
+    fn require<T>(&self, value: Option<T>, path: &str, key: &str) -> Result<T, SceneError> {
+        value.ok_or_else(|| self.error(&join(path, key), "missing".to_string()))
+    }
+
+    fn expected<T>(&self, path: &str, key: &str, expected: &str, value: &Value) -> Result<T, SceneError> {
+        Err(self.error(&join(path, key), format!("expected {}, found {}", expected, type_name(value))))
+    }
+
+    fn table<'t>(&self, value: &'t Value, path: &str, key: &str) -> Result<&'t Table, SceneError> {
+        match value {
+            Value::Table(table) => Ok(table),
+            _ => self.expected(path, key, "a table", value),
+        }
+    }
+
+    fn number(&self, table: &Table, path: &str, key: &str) -> Result<Option<f64>, SceneError> {
+        match table.get(key) {
+            None => Ok(None),
+            Some(Value::Float(x)) => Ok(Some(*x)),
+            Some(Value::Integer(i)) => Ok(Some(*i as f64)),
+            Some(value) => self.expected(path, key, "a number", value),
+        }
+    }
+
+    // integer of at least `min`
+    fn integer(&self, table: &Table, path: &str, key: &str, min: i64) -> Result<Option<i64>, SceneError> {
+        match table.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) if *i >= min => Ok(Some(*i)),
+            Some(Value::Integer(i)) => Err(self.error(&join(path, key), format!("{} is less than {}", i, min))),
+            Some(value) => self.expected(path, key, "an integer", value),
+        }
+    }
+
+    // an integer that also has to fit in `T`
+    fn sized<T: TryFrom<i64>>(&self, table: &Table, path: &str, key: &str, min: i64) -> Result<Option<T>, SceneError> {
+        match self.integer(table, path, key, min)? {
+            Some(i) => T::try_from(i).map(Some).map_err(|_| self.error(&join(path, key), format!("{} is too large", i))),
+            None => Ok(None),
+        }
+    }
+
+    fn string<'t>(&self, table: &'t Table, path: &str, key: &str) -> Result<Option<&'t str>, SceneError> {
+        match table.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(value) => self.expected(path, key, "a string", value),
+        }
+    }
+
+    fn boolean(&self, table: &Table, path: &str, key: &str) -> Result<Option<bool>, SceneError> {
+        match table.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(*b)),
+            Some(value) => self.expected(path, key, "true or false", value),
+        }
+    }
+
+    fn triple(&self, value: &Value, path: &str, key: &str) -> Result<[f64; 3], SceneError> {
+        let numbers: Option<Vec<f64>> = match value {
+            Value::Array(array) if array.len() == 3 => array.iter()
+                .map(|v| match v {
+                    Value::Float(x) => Some(*x),
+                    Value::Integer(i) => Some(*i as f64),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        };
+        match numbers {
+            Some(n) => Ok([n[0], n[1], n[2]]),
+            None => self.expected(path, key, "an array of 3 numbers", value),
+        }
+    }
+
+    fn vector(&self, table: &Table, path: &str, key: &str) -> Result<Option<Vector3>, SceneError> {
+        table.get(key)
+            .map(|value| self.triple(value, path, key).map(|[x, y, z]| Vector3::new(x, y, z)))
+            .transpose()
+    }
+
+    fn color(&self, table: &Table, path: &str, key: &str) -> Result<Option<RGBColor>, SceneError> {
+        table.get(key)
+            .map(|value| self.triple(value, path, key).map(|[r, g, b]| RGBColor::new(r, g, b)))
+            .transpose()
+    }
+
+    // one of `options`, as a string
+    fn choice<T: Copy>(&self, table: &Table, path: &str, key: &str, options: &[(&str, T)]) -> Result<Option<T>, SceneError> {
+        let name = match self.string(table, path, key)? {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+        match options.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => Ok(Some(*value)),
+            None => {
+                let names: Vec<&str> = options.iter().map(|(n, _)| *n).collect();
+                Err(self.error(&join(path, key), format!("unknown value `{}`, expected one of {}", name, names.join(", "))))
+            }
+        }
+    }
+
+    fn kind<'t>(&self, table: &'t Table, path: &str, kinds: &[&str]) -> Result<&'t str, SceneError> {
+        let kind = self.string(table, path, "type")?;
+        let kind = self.require(kind, path, "type")?;
+        match kinds.contains(&kind) {
+            true => Ok(kind),
+            false => Err(self.error(&join(path, "type"), format!("unknown type `{}`, expected one of {}", kind, kinds.join(", ")))),
+        }
+    }
+
+    fn scene(&mut self) -> Result<Scene, SceneError> {
+        let root = self.root;
+        self.check_keys(root, "", &["render", "camera", "background", "fog", "textures", "materials", "shapes", "lights", "volumes"])?;
+
+        let settings = match root.get("render") {
+            Some(value) => self.settings(self.table(value, "", "render")?)?,
+            None => RenderSettings::default(),
+        };
+        self.rng = Rng::with_seed(settings.seed);
+        let camera = match root.get("camera") {
+            Some(value) => self.camera(self.table(value, "", "camera")?, settings.aspect_ratio())?,
+            None => return Err(self.error("camera", "missing".to_string())),
+        };
+
+        let mut world = World::new();
+        if let Some(value) = root.get("background") {
+            world.background = self.background(self.table(value, "", "background")?)?;
+        }
+        if let Some(value) = root.get("fog") {
+            let t = self.table(value, "", "fog")?;
+            self.check_keys(t, "fog", &["density", "albedo", "anisotropy", "distance"])?;
+            world.fog = Some(self.medium(t, "fog")?);
+            if let Some(distance) = self.number(t, "fog", "distance")? {
+                if distance <= 0.0 {
+                    return Err(self.error("fog.distance", "must be positive".to_string()))
+                }
+                world.fog_distance = distance;
+            }
+        }
+
+        // build every named texture and material, even unused ones, so mistakes in them are reported
+        for (section, is_texture) in [("textures", true), ("materials", false)] {
+            if let Some(value) = root.get(section) {
+                for name in self.table(value, "", section)?.keys() {
+                    match is_texture {
+                        true => self.named_texture(name, section).map(|_| ())?,
+                        false => self.named_material(name, section).map(|_| ())?,
+                    }
+                }
+            }
+        }
+
+        for section in ["shapes", "lights", "volumes"] {
+            match root.get(section) {
+                Some(Value::Array(shapes)) => {
+                    for (i, shape) in shapes.iter().enumerate() {
+                        let path = format!("{}[{}]", section, i);
+                        let table = match shape {
+                            Value::Table(table) => table,
+                            _ => return Err(self.error(&path, format!("expected a table, found {}", type_name(shape)))),
+                        };
+                        match section {
+                            "volumes" => world.add_volume(self.volume(table, &path)?),
+                            _ => self.shape(table, &path, section == "lights", &mut world)?,
+                        }
+                    }
+                }
+                Some(value) => return self.expected("", section, "an array of tables", value),
+                None => (),
+            }
+        }
+
+        world.build_bvh();
+        Ok(Scene {world, camera, settings})
+    }
+
+    fn settings(&self, t: &Table) -> Result<RenderSettings, SceneError> {
+        let path = "render";
+        self.check_keys(t, path, &["width", "height", "samples_per_pixel", "max_depth", "seed", "threads", "aovs", "spectral"])?;
+        let default = RenderSettings::default();
+
+        let width = self.sized(t, path, "width", 1)?.unwrap_or(default.width);
+        let mut settings = RenderSettings::with_aspect_ratio(width, default.aspect_ratio());
+        if let Some(height) = self.sized(t, path, "height", 1)? {
+            settings.height = height;
+        }
+        settings.height = usize::max(settings.height, 1);
+        if let Some(spp) = self.sized(t, path, "samples_per_pixel", 1)? {
+            settings.samples_per_pixel = spp;
+        }
+        if let Some(depth) = self.sized(t, path, "max_depth", 1)? {
+            settings.max_depth = depth;
+        }
+        if let Some(seed) = self.sized(t, path, "seed", 0)? {
+            settings.seed = seed;
+        }
+        if let Some(threads) = self.sized(t, path, "threads", 1)? {
+            settings.threads = threads;
+        }
+        settings.spectral = self.boolean(t, path, "spectral")?.unwrap_or(false);
+        match t.get("aovs") {
+            Some(Value::Array(names)) => {
+                for (i, name) in names.iter().enumerate() {
+                    let key = format!("aovs[{}]", i);
+                    match name {
+                        Value::String(s) if s == "depth" => settings.aovs.depth = true,
+                        Value::String(s) if s == "normal" => settings.aovs.normal = true,
+                        Value::String(s) => return Err(self.error(&join(path, &key), format!("unknown aov `{}`, expected depth or normal", s))),
+                        _ => return self.expected(path, &key, "a string", name),
+                    }
+                }
+            }
+            Some(value) => return self.expected(path, "aovs", "an array of strings", value),
+            None => (),
+        }
+        Ok(settings)
+    }
+
+    fn camera(&self, t: &Table, aspect_ratio: f64) -> Result<Camera, SceneError> {
+        let path = "camera";
+        self.check_keys(t, path, &["lookfrom", "lookat", "vup", "vfov", "aperture", "focus_dist", "shutter_open", "shutter_close"])?;
+        let lookfrom = self.vector(t, path, "lookfrom")?;
+        let lookfrom = self.require(lookfrom, path, "lookfrom")?;
+        let lookat = self.vector(t, path, "lookat")?;
+        let lookat = self.require(lookat, path, "lookat")?;
+        let vup = self.vector(t, path, "vup")?.unwrap_or(Vector3::new(0.0, 1.0, 0.0));
+        let vfov = self.number(t, path, "vfov")?.unwrap_or(40.0);
+        let aperture = self.number(t, path, "aperture")?.unwrap_or(0.0);
+        // in focus at the point looked at unless told otherwise
+        let focus_dist = self.number(t, path, "focus_dist")?.unwrap_or((lookfrom - lookat).length());
+
+        if (lookfrom - lookat).near_zero() {
+            return Err(self.error("camera.lookat", "same as camera.lookfrom".to_string()))
+        }
+        if vup.cross(lookfrom - lookat).near_zero() {
+            return Err(self.error("camera.vup", "parallel to the view direction".to_string()))
+        }
+        if vfov <= 0.0 || vfov >= 180.0 {
+            return Err(self.error("camera.vfov", "must be between 0 and 180".to_string()))
+        }
+        if focus_dist <= 0.0 {
+            return Err(self.error("camera.focus_dist", "must be positive".to_string()))
+        }
+        if aperture < 0.0 {
+            return Err(self.error("camera.aperture", "can't be negative".to_string()))
+        }
+        let shutter_open = self.number(t, path, "shutter_open")?.unwrap_or(0.0);
+        let shutter_close = self.number(t, path, "shutter_close")?.unwrap_or(shutter_open);
+        if shutter_close < shutter_open {
+            return Err(self.error("camera.shutter_close", "before camera.shutter_open".to_string()))
+        }
+
+        let mut camera = Camera::new(lookfrom, lookat, vup, vfov, aspect_ratio, aperture, focus_dist);
+        camera.set_shutter(shutter_open, shutter_close);
+        Ok(camera)
+    }
+
+    fn background(&self, t: &Table) -> Result<Background, SceneError> {
+        let path = "background";
+        let kind = self.kind(t, path, &["solid", "gradient", "environment", "sky"])?;
+        Ok(match kind {
+            "solid" => {
+                self.check_keys(t, path, &["type", "color"])?;
+                let color = self.color(t, path, "color")?;
+                Background::Solid(self.require(color, path, "color")?)
+            }
+            "gradient" => {
+                self.check_keys(t, path, &["type", "bottom", "top"])?;
+                let bottom = self.color(t, path, "bottom")?;
+                let top = self.color(t, path, "top")?;
+                Background::Gradient {bottom: self.require(bottom, path, "bottom")?, top: self.require(top, path, "top")?}
+            }
+            "environment" => {
+                self.check_keys(t, path, &["type", "file", "intensity", "rotation"])?;
+                let image = self.image(t, path, true)?;
+                let intensity = self.number(t, path, "intensity")?.unwrap_or(1.0);
+                let rotation = self.number(t, path, "rotation")?.unwrap_or(0.0);
+                Background::Environment(Arc::new(EnvironmentMap::new(image, intensity, rotation)))
+            }
+            _ => {
+                self.check_keys(t, path, &["type", "sun_direction", "turbidity", "intensity", "ground"])?;
+                let sun = self.vector(t, path, "sun_direction")?;
+                let turbidity = self.number(t, path, "turbidity")?.unwrap_or(3.0);
+                let intensity = self.number(t, path, "intensity")?.unwrap_or(1.0);
+                let ground = self.color(t, path, "ground")?.unwrap_or(RGBColor::new(0.3, 0.3, 0.3));
+                Background::Sky(Sky::new(self.require(sun, path, "sun_direction")?, turbidity, intensity, ground))
+            }
+        })
+    }
+
+    // the image named by the `file` key, with at least one pixel
+    fn image(&self, t: &Table, path: &str, srgb: bool) -> Result<crate::framebuffer::Framebuffer, SceneError> {
+        let file = self.string(t, path, "file")?;
+        let file = self.require(file, path, "file")?;
+        let image = load_image(&self.folder.join(file), srgb).map_err(|e| self.error(&join(path, "file"), format!("{}: {}", file, e)))?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err(self.error(&join(path, "file"), format!("{}: empty image", file)))
+        }
+        Ok(image)
+    }
+
+    // a color, the name of a texture, or an inline texture table
+    fn texture(&mut self, value: &Value, path: &str, key: &str) -> Result<Texture, SceneError> {
+        match value {
+            Value::Array(_) => self.triple(value, path, key).map(|[r, g, b]| Texture::Solid(RGBColor::new(r, g, b))),
+            Value::String(name) => self.named_texture(name, &join(path, key)),
+            Value::Table(table) => self.texture_table(table, &join(path, key)),
+            _ => self.expected(path, key, "a color, a texture name or a texture table", value),
+        }
+    }
+
+    fn optional_texture(&mut self, t: &Table, path: &str, key: &str, default: RGBColor) -> Result<Texture, SceneError> {
+        match t.get(key) {
+            Some(value) => self.texture(value, path, key),
+            None => Ok(Texture::Solid(default)),
+        }
+    }
+
+    // a number from 0 to 1, or a texture of them
+    fn factor(&mut self, t: &Table, path: &str, key: &str) -> Result<Option<Texture>, SceneError> {
+        match t.get(key) {
+            None => Ok(None),
+            Some(Value::Float(_) | Value::Integer(_)) => match self.number(t, path, key)? {
+                Some(v) if !(0.0..=1.0).contains(&v) => Err(self.error(&join(path, key), "must be between 0 and 1".to_string())),
+                v => Ok(v.map(|v| Texture::Solid(RGBColor::new(v, v, v)))),
+            },
+            Some(value) => self.texture(value, path, key).map(Some),
+        }
+    }
+
+    fn required_texture(&mut self, t: &Table, path: &str, key: &str) -> Result<Texture, SceneError> {
+        let value = self.require(t.get(key), path, key)?;
+        self.texture(value, path, key)
+    }
+
+    // `key` is where the name was found
+    fn named_texture(&mut self, name: &str, key: &str) -> Result<Texture, SceneError> {
+        if let Some(texture) = self.textures.get(name) {
+            return Ok(texture.clone())
+        }
+        let root = self.root;
+        let value = match root.get("textures").and_then(|t| t.get(name)) {
+            Some(value) => value,
+            None => return Err(self.error(key, format!("unknown texture `{}`", name))),
+        };
+        let path = join("textures", name);
+        if !self.resolving.insert(path.clone()) {
+            return Err(self.error(key, format!("texture `{}` refers to itself", name)))
+        }
+        let texture = self.texture_table(self.table(value, "textures", name)?, &path)?;
+        self.resolving.remove(&path);
+        self.textures.insert(name.to_string(), texture.clone());
+        Ok(texture)
+    }
+
+    fn texture_table(&mut self, t: &Table, path: &str) -> Result<Texture, SceneError> {
+        let kind = self.kind(t, path, &["solid", "checker", "uv_checker", "image", "noise"])?;
+        Ok(match kind {
+            "solid" => {
+                self.check_keys(t, path, &["type", "color"])?;
+                let color = self.color(t, path, "color")?;
+                Texture::Solid(self.require(color, path, "color")?)
+            }
+            "checker" => {
+                self.check_keys(t, path, &["type", "scale", "even", "odd"])?;
+                let scale = self.number(t, path, "scale")?.unwrap_or(1.0);
+                let even = Box::new(self.required_texture(t, path, "even")?);
+                let odd = Box::new(self.required_texture(t, path, "odd")?);
+                Texture::Checker {scale, even, odd}
+            }
+            "uv_checker" => {
+                self.check_keys(t, path, &["type", "columns", "rows", "even", "odd"])?;
+                let columns = self.number(t, path, "columns")?.unwrap_or(8.0);
+                let rows = self.number(t, path, "rows")?.unwrap_or(columns);
+                let even = Box::new(self.required_texture(t, path, "even")?);
+                let odd = Box::new(self.required_texture(t, path, "odd")?);
+                Texture::UvChecker {columns, rows, even, odd}
+            }
+            "image" => {
+                self.check_keys(t, path, &["type", "file", "srgb", "wrap", "filter"])?;
+                let srgb = self.boolean(t, path, "srgb")?.unwrap_or(true);
+                let mut texture = ImageTexture::new(self.image(t, path, srgb)?);
+                let wraps = [("repeat", WrapMode::Repeat), ("clamp", WrapMode::Clamp), ("mirror", WrapMode::Mirror)];
+                texture.wrap = self.choice(t, path, "wrap", &wraps)?.unwrap_or(texture.wrap);
+                let filters = [("nearest", Filter::Nearest), ("bilinear", Filter::Bilinear)];
+                texture.filter = self.choice(t, path, "filter", &filters)?.unwrap_or(texture.filter);
+                Texture::Image(Arc::new(texture))
+            }
+            _ => {
+                self.check_keys(t, path, &["type", "pattern", "octaves", "scale", "low", "high", "seed"])?;
+                let octaves = self.sized(t, path, "octaves", 1)?.unwrap_or(7);
+                let patterns = [
+                    ("perlin", NoisePattern::Perlin), ("turbulence", NoisePattern::Turbulence(octaves)),
+                    ("fbm", NoisePattern::Fbm(octaves)), ("worley", NoisePattern::Worley),
+                    ("marble", NoisePattern::Marble(octaves)), ("wood", NoisePattern::Wood),
+                ];
+                let pattern = self.choice(t, path, "pattern", &patterns)?;
+                let pattern = self.require(pattern, path, "pattern")?;
+                let scale = self.number(t, path, "scale")?.unwrap_or(1.0);
+                let low = self.optional_texture(t, path, "low", RGBColor::new(0.0, 0.0, 0.0))?;
+                let high = self.optional_texture(t, path, "high", RGBColor::new(1.0, 1.0, 1.0))?;
+                let rng = match self.integer(t, path, "seed", 0)? {
+                    Some(seed) => Rng::with_seed(seed as u64),
+                    None => Rng::with_seed(self.rng.u64(..)),
+                };
+                Texture::Noise(Arc::new(NoiseTexture::new(&rng, pattern, scale, low, high)))
+            }
+        })
+    }
+
+    // the name of a material, or an inline material table
+    fn material(&mut self, value: &Value, path: &str, key: &str) -> Result<Material, SceneError> {
+        match value {
+            Value::String(name) => self.named_material(name, &join(path, key)),
+            Value::Table(table) => self.material_table(table, &join(path, key)),
+            _ => self.expected(path, key, "a material name or a material table", value),
+        }
+    }
+
+    fn named_material(&mut self, name: &str, key: &str) -> Result<Material, SceneError> {
+        if let Some(material) = self.materials.get(name) {
+            return Ok(material.clone())
+        }
+        let root = self.root;
+        let value = match root.get("materials").and_then(|t| t.get(name)) {
+            Some(value) => value,
+            None => return Err(self.error(key, format!("unknown material `{}`", name))),
+        };
+        let path = join("materials", name);
+        if !self.resolving.insert(path.clone()) {
+            return Err(self.error(key, format!("material `{}` refers to itself", name)))
+        }
+        let material = self.material_table(self.table(value, "materials", name)?, &path)?;
+        self.resolving.remove(&path);
+        self.materials.insert(name.to_string(), material.clone());
+        Ok(material)
+    }
+
+    fn material_table(&mut self, t: &Table, path: &str) -> Result<Material, SceneError> {
+        let kind = self.kind(t, path, &["lambertian", "metal", "conductor", "dielectric", "principled", "light", "bump"])?;
+        Ok(match kind {
+            "lambertian" => {
+                self.check_keys(t, path, &["type", "albedo"])?;
+                Material::Lambertian(self.required_texture(t, path, "albedo")?)
+            }
+            "metal" => {
+                self.check_keys(t, path, &["type", "albedo", "fuzz"])?;
+                let albedo = self.required_texture(t, path, "albedo")?;
+                Material::Metal(albedo, self.number(t, path, "fuzz")?.unwrap_or(0.0))
+            }
+            "conductor" => {
+                self.check_keys(t, path, &["type", "metal", "eta", "k", "roughness"])?;
+                let metals = [("gold", ComplexIor::GOLD), ("copper", ComplexIor::COPPER),
+                              ("aluminium", ComplexIor::ALUMINIUM), ("silver", ComplexIor::SILVER)];
+                // the index of a named metal, or given by its real and imaginary parts
+                let ior = match self.choice(t, path, "metal", &metals)? {
+                    Some(ior) => ComplexIor::new(self.color(t, path, "eta")?.unwrap_or(ior.eta), self.color(t, path, "k")?.unwrap_or(ior.k)),
+                    None => {
+                        let (eta, k) = (self.color(t, path, "eta")?, self.color(t, path, "k")?);
+                        ComplexIor::new(self.require(eta, path, "eta")?, self.require(k, path, "k")?)
+                    }
+                };
+                Material::Conductor(ior, self.roughness(t, path)?)
+            }
+            "dielectric" => {
+                self.check_keys(t, path, &["type", "ior", "cauchy", "sellmeier", "roughness", "absorption_color", "absorption_distance"])?;
+                let ior = self.ior(t, path)?;
+                let absorption = self.absorption(t, path)?;
+                match self.roughness(t, path)? {
+                    0.0 => Material::Dielectric(ior, absorption),
+                    _ if ior.is_dispersive() => return Err(self.error(&join(path, "roughness"), "can't be combined with dispersion".to_string())),
+                    roughness => Material::RoughDielectric(ior.mean(), roughness, absorption),
+                }
+            }
+            "principled" => {
+                self.check_keys(t, path, &["type", "base_color", "metallic", "roughness", "specular", "sheen", "clearcoat",
+                                           "clearcoat_roughness", "transmission", "subsurface", "ior"])?;
+                let mut principled = Principled::new(self.optional_texture(t, path, "base_color", RGBColor::new(0.8, 0.8, 0.8))?);
+                for (key, texture) in [("metallic", &mut principled.metallic), ("roughness", &mut principled.roughness),
+                                       ("specular", &mut principled.specular), ("sheen", &mut principled.sheen),
+                                       ("clearcoat", &mut principled.clearcoat), ("clearcoat_roughness", &mut principled.clearcoat_roughness),
+                                       ("transmission", &mut principled.transmission), ("subsurface", &mut principled.subsurface)] {
+                    if let Some(value) = self.factor(t, path, key)? {
+                        *texture = value;
+                    }
+                }
+                principled.ior = self.number(t, path, "ior")?.unwrap_or(principled.ior);
+                Material::Principled(Box::new(principled))
+            }
+            "light" => {
+                self.check_keys(t, path, &["type", "color", "strength"])?;
+                self.light(t, path)?
+            }
+            _ => {
+                self.check_keys(t, path, &["type", "base", "height", "strength"])?;
+                let base = self.require(t.get("base"), path, "base")?;
+                let base = self.material(base, path, "base")?;
+                let height = self.required_texture(t, path, "height")?;
+                Material::Bump(Box::new(base), height, self.number(t, path, "strength")?.unwrap_or(1.0))
+            }
+        })
+    }
+
+    // a number or the name of a glass, or the coefficients of Cauchy's or Sellmeier's equation
+    fn ior(&self, t: &Table, path: &str) -> Result<Ior, SceneError> {
+        let given: Vec<&str> = ["ior", "cauchy", "sellmeier"].into_iter().filter(|key| t.contains_key(*key)).collect();
+        if given.len() > 1 {
+            return Err(self.error(&join(path, given[1]), format!("can't be combined with {}", given[0])))
+        }
+        match given.first() {
+            Some(&"ior") => {
+                let ior = match t["ior"] {
+                    Value::String(_) => self.choice(t, path, "ior", &[("bk7", Ior::BK7), ("diamond", Ior::DIAMOND)])?,
+                    _ => self.number(t, path, "ior")?.map(Ior::Constant),
+                };
+                self.require(ior, path, "ior")
+            }
+            Some(&"cauchy") => {
+                let (table, path) = (self.table(&t["cauchy"], path, "cauchy")?, join(path, "cauchy"));
+                self.check_keys(table, &path, &["a", "b"])?;
+                let (a, b) = (self.number(table, &path, "a")?, self.number(table, &path, "b")?);
+                Ok(Ior::Cauchy(self.require(a, &path, "a")?, self.require(b, &path, "b")?))
+            }
+            Some(_) => {
+                let (table, path) = (self.table(&t["sellmeier"], path, "sellmeier")?, join(path, "sellmeier"));
+                self.check_keys(table, &path, &["b", "c"])?;
+                let b = self.triple(self.require(table.get("b"), &path, "b")?, &path, "b")?;
+                let c = self.triple(self.require(table.get("c"), &path, "c")?, &path, "c")?;
+                Ok(Ior::Sellmeier(b, c))
+            }
+            None => Ok(Ior::Constant(1.5)),
+        }
+    }
+
+    // absorption per unit length of glass letting through `absorption_color` of the light
+    // going through `absorption_distance` units
+    fn absorption(&self, t: &Table, path: &str) -> Result<RGBColor, SceneError> {
+        let color = match self.color(t, path, "absorption_color")? {
+            Some(color) => color,
+            None => return Ok(RGBColor::new(0.0, 0.0, 0.0)),
+        };
+        if [color.r(), color.g(), color.b()].iter().any(|c| *c <= 0.0 || *c > 1.0) {
+            return Err(self.error(&join(path, "absorption_color"), "must be above 0 and at most 1".to_string()))
+        }
+        let distance = self.number(t, path, "absorption_distance")?.unwrap_or(1.0);
+        if distance <= 0.0 {
+            return Err(self.error(&join(path, "absorption_distance"), "must be positive".to_string()))
+        }
+        Ok(Material::absorption(color, distance))
+    }
+
+    fn roughness(&self, t: &Table, path: &str) -> Result<f64, SceneError> {
+        match self.number(t, path, "roughness")? {
+            Some(r) if !(0.0..=1.0).contains(&r) => Err(self.error(&join(path, "roughness"), "must be between 0 and 1".to_string())),
+            r => Ok(r.unwrap_or(0.0)),
+        }
+    }
+
+    // of a table whose keys are checked by the caller
+    fn medium(&mut self, t: &Table, path: &str) -> Result<Medium, SceneError> {
+        let density = self.number(t, path, "density")?;
+        let density = self.require(density, path, "density")?;
+        if density <= 0.0 {
+            return Err(self.error(&join(path, "density"), "must be positive".to_string()))
+        }
+        let albedo = self.optional_texture(t, path, "albedo", RGBColor::new(1.0, 1.0, 1.0))?;
+        Ok(Medium::new(density, albedo, self.phase(t, path)?))
+    }
+
+    fn phase(&self, t: &Table, path: &str) -> Result<PhaseFunction, SceneError> {
+        match self.number(t, path, "anisotropy")? {
+            None | Some(0.0) => Ok(PhaseFunction::Isotropic),
+            Some(g) if g.abs() < 1.0 => Ok(PhaseFunction::HenyeyGreenstein(g)),
+            Some(_) => Err(self.error(&join(path, "anisotropy"), "must be between -1 and 1".to_string())),
+        }
+    }
+
+    // grid read from the file named by `key`, with the volume's `size` for raw files
+    fn grid(&self, t: &Table, path: &str, key: &str) -> Result<VoxelGrid, SceneError> {
+        let size = match t.get("size") {
+            Some(value) => {
+                let [x, y, z] = self.triple(value, path, "size")?;
+                if [x, y, z].iter().any(|n| n.fract() != 0.0 || *n < 1.0) {
+                    return Err(self.error(&join(path, "size"), "expected 3 positive integers".to_string()))
+                }
+                Some([x as usize, y as usize, z as usize])
+            }
+            None => None,
+        };
+        let file = self.string(t, path, key)?;
+        let file = self.require(file, path, key)?;
+        VoxelGrid::load(&self.folder.join(file), size).map_err(|e| self.error(&join(path, key), e.to_string()))
+    }
+
+    // density grid stretched from `min` to `max`, glowing with an `emission` color or by
+    // the `temperature` of another grid
+    fn volume(&mut self, t: &Table, path: &str) -> Result<GridVolume, SceneError> {
+        self.check_keys(t, path, &["file", "size", "min", "max", "density", "albedo", "anisotropy",
+                                   "emission", "temperature", "kelvin", "strength"])?;
+        let density = self.grid(t, path, "file")?;
+        let min = self.vector(t, path, "min")?;
+        let min = self.require(min, path, "min")?;
+        let max = self.vector(t, path, "max")?;
+        let max = self.require(max, path, "max")?;
+        if max.x <= min.x || max.y <= min.y || max.z <= min.z {
+            return Err(self.error(&join(path, "max"), "must be above min on every axis".to_string()))
+        }
+        let scale = self.number(t, path, "density")?.unwrap_or(1.0);
+        if scale < 0.0 {
+            return Err(self.error(&join(path, "density"), "can't be negative".to_string()))
+        }
+        let albedo = self.optional_texture(t, path, "albedo", RGBColor::new(1.0, 1.0, 1.0))?;
+        let phase = self.phase(t, path)?;
+
+        let emission = match (self.color(t, path, "emission")?, t.contains_key("temperature")) {
+            (Some(_), true) => return Err(self.error(&join(path, "temperature"), "can't be combined with emission".to_string())),
+            (Some(color), false) => Emission::Density(color),
+            (None, true) => Emission::Blackbody {
+                temperature: self.grid(t, path, "temperature")?,
+                kelvin: self.number(t, path, "kelvin")?.unwrap_or(1.0),
+                strength: self.number(t, path, "strength")?.unwrap_or(1.0),
+            },
+            (None, false) => Emission::None,
+        };
+        Ok(GridVolume::new(density, Aabb::new(min, max), scale, albedo, phase, emission))
+    }
+
+    // scaled, then rotated about the x, y and z axes in that order, then translated
+    fn transform(&self, t: &Table, path: &str) -> Result<Transform, SceneError> {
+        self.check_keys(t, path, &["translate", "rotate", "scale"])?;
+        let mut transform = match t.get("scale") {
+            Some(Value::Integer(i)) if *i != 0 => Transform::scale(Vector3::new(*i as f64, *i as f64, *i as f64)),
+            Some(Value::Float(x)) if *x != 0.0 => Transform::scale(Vector3::new(*x, *x, *x)),
+            Some(value @ Value::Array(_)) => {
+                let [x, y, z] = self.triple(value, path, "scale")?;
+                if x * y * z == 0.0 {
+                    return Err(self.error(&join(path, "scale"), "can't be zero".to_string()))
+                }
+                Transform::scale(Vector3::new(x, y, z))
+            }
+            Some(Value::Integer(_) | Value::Float(_)) => return Err(self.error(&join(path, "scale"), "can't be zero".to_string())),
+            Some(value) => return self.expected(path, "scale", "a number or an array of 3 numbers", value),
+            None => Transform::identity(),
+        };
+        if let Some(angles) = self.vector(t, path, "rotate")? {
+            for (i, axis) in [Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)].into_iter().enumerate() {
+                transform = Transform::rotate(axis, angles[i]) * transform;
+            }
+        }
+        if let Some(offset) = self.vector(t, path, "translate")? {
+            transform = Transform::translate(offset) * transform;
+        }
+        Ok(transform)
+    }
+
+    fn light(&self, t: &Table, path: &str) -> Result<Material, SceneError> {
+        let color = self.color(t, path, "color")?.unwrap_or(RGBColor::new(1.0, 1.0, 1.0));
+        Ok(Material::DiffuseLight(color, self.number(t, path, "strength")?.unwrap_or(1.0)))
+    }
+
+    // shapes take a `material`, lights a `color` and `strength` instead
+    fn shape(&mut self, t: &Table, path: &str, light: bool, world: &mut World) -> Result<(), SceneError> {
+        let kind = self.kind(t, path, &["sphere", "plane", "quad", "disk", "triangle", "obj"])?;
+        let mut allowed = match kind {
+            "sphere" => vec!["center", "radius"],
+            "plane" => vec!["point", "normal"],
+            "quad" => vec!["corner", "edge_u", "edge_v"],
+            "disk" => vec!["center", "normal", "radius"],
+            "triangle" => vec!["a", "b", "c"],
+            _ => vec!["file"],
+        };
+        allowed.push("type");
+        // lights are sampled where they are, so they can't be instances
+        match light {
+            true => allowed.extend(["color", "strength"]),
+            false => allowed.extend(["material", "medium", "transform", "end_transform", "motion"]),
+        }
+        if let Some(key) = ["transform", "end_transform", "motion"].into_iter().find(|key| light && t.contains_key(*key)) {
+            return Err(self.error(&join(path, key), "lights can't be transformed or moved".to_string()))
+        }
+        self.check_keys(t, path, &allowed)?;
+
+        // shapes with a medium are filled with it instead of having a surface
+        let medium = match t.get("medium") {
+            Some(value) => {
+                let (t, path) = (self.table(value, path, "medium")?, join(path, "medium"));
+                self.check_keys(t, &path, &["density", "albedo", "anisotropy"])?;
+                Some(self.medium(t, &path)?)
+            }
+            None => None,
+        };
+        let material = match (light, t.get("material"), &medium) {
+            (true, _, _) => Some(self.light(t, path)?),
+            (false, Some(_), Some(_)) => return Err(self.error(&join(path, "medium"), "can't be combined with material".to_string())),
+            (false, Some(value), None) => Some(self.material(value, path, "material")?),
+            (false, None, Some(medium)) => Some(medium.material.clone()),
+            // meshes keep the materials of their mtl files
+            (false, None, None) if kind == "obj" => None,
+            (false, None, None) => return Err(self.error(&join(path, "material"), "missing".to_string())),
+        };
+        let mut transforms = Vec::new();
+        for key in ["transform", "end_transform"] {
+            transforms.push(match t.get(key) {
+                Some(value) => Some(self.transform(self.table(value, path, key)?, &join(path, key))?),
+                None => None,
+            });
+        }
+        let motion = match (transforms[0], transforms[1], self.vector(t, path, "motion")?) {
+            (_, Some(_), Some(_)) => return Err(self.error(&join(path, "motion"), "can't be combined with end_transform".to_string())),
+            (start, Some(end), None) => Some(Motion::Animated(Box::new(AnimatedTransform::new(start.unwrap_or_default(), end)))),
+            (start, None, Some(offset)) => Some(Motion::Linear(start.unwrap_or_default(), offset)),
+            (Some(transform), None, None) => Some(Motion::Fixed(transform)),
+            (None, None, None) => None,
+        };
+        // transformed and moving shapes are added as instances, inside the volumes they bound
+        let add = |world: &mut World, shape: Shape| {
+            let shape = match &motion {
+                Some(motion) => Shape::Instance(Arc::new(Instance::moving(Arc::new(shape), motion.clone()))),
+                None => shape,
+            };
+            match &medium {
+                Some(medium) => world.add(Shape::Volume(Arc::new(Volume::new(Arc::new(shape), medium.clone())))),
+                None => world.add(shape),
+            }
+        };
+        let vector = |key: &str| self.vector(t, path, key).and_then(|v| self.require(v, path, key));
+        let number = |key: &str| self.number(t, path, key).and_then(|v| self.require(v, path, key));
+
+        let shape = match kind {
+            "sphere" => Shape::Sphere {center: vector("center")?, radius: number("radius")?, material: material.unwrap()},
+            "plane" => Shape::Plane {point: vector("point")?, normal: vector("normal")?, material: material.unwrap()},
+            "quad" => Shape::Quad {corner: vector("corner")?, edge_u: vector("edge_u")?, edge_v: vector("edge_v")?, material: material.unwrap()},
+            "disk" => Shape::Disk {center: vector("center")?, normal: vector("normal")?, radius: number("radius")?, material: material.unwrap()},
+            "triangle" => Shape::Triangle {a: vector("a")?, b: vector("b")?, c: vector("c")?, material: material.unwrap()},
+            _ => {
+                let file = self.string(t, path, "file")?;
+                let file = self.folder.join(self.require(file, path, "file")?);
+                let meshes = match (material, self.meshes.get(&file)) {
+                    (None, Some(meshes)) => meshes.clone(),
+                    (material, _) => {
+                        let mut model = ObjModel::load(&file)
+                            .map_err(|e| self.error(&join(path, "file"), e.to_string()))?;
+                        if let Some(material) = &material {
+                            for group in &mut model.groups {
+                                group.material = material.clone();
+                            }
+                        }
+                        let meshes: Vec<_> = model.to_meshes().into_iter().map(Arc::new).collect();
+                        if material.is_none() {
+                            self.meshes.insert(file, meshes.clone());
+                        }
+                        meshes
+                    }
+                };
+                for mesh in meshes {
+                    add(world, Shape::Mesh(mesh));
+                }
+                return Ok(())
+            }
+        };
+        add(world, shape);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{Scene, SceneError};
+    use crate::{hittable::Hittable, ray::Ray, vector3::Vector3};
+
+    fn error(source: &str) -> String {
+        match Scene::parse(source, "test.toml", Path::new("")) {
+            Ok(_) => panic!("no error for {}", source),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn load_test() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/cornell.toml");
+        let scene = Scene::load(&path).unwrap_or_else(|e| panic!("{}", e));
+
+        assert_eq!(8, scene.world.len());
+        assert_eq!(1, scene.world.light_count());
+        assert_eq!((400, 400, 200), (scene.settings.width, scene.settings.height, scene.settings.samples_per_pixel));
+    }
+    #[test]
+    fn error_test() {
+        let camera = "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\n";
+
+        assert!(matches!(Scene::parse("[camera", "test.toml", Path::new("")), Err(SceneError::Parse {..})));
+        assert_eq!("test.toml: camera: missing", error(""));
+        assert!(error(&format!("{}[cameras]", camera)).starts_with("test.toml: cameras: unknown key, expected one of render, camera"));
+        assert_eq!("test.toml: camera.lookat: expected an array of 3 numbers, found an array", error("[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0]"));
+        assert_eq!("test.toml: render.samples_per_pixel: 0 is less than 1", error(&format!("{}[render]\nsamples_per_pixel = 0", camera)));
+        assert_eq!("test.toml: render.samples_per_pixel: 4294967297 is too large", error(&format!("{}[render]\nsamples_per_pixel = 4294967297", camera)));
+        assert_eq!("test.toml: render.max_depth: 2147483648 is too large", error(&format!("{}[render]\nmax_depth = 2147483648", camera)));
+        assert_eq!("test.toml: camera.vup: parallel to the view direction", error("[camera]\nlookfrom = [0, 5, 0]\nlookat = [0, 0, 0]"));
+        assert_eq!("test.toml: camera.vfov: must be between 0 and 180", error(&format!("{}vfov = 180", camera)));
+        assert_eq!("test.toml: camera.focus_dist: must be positive", error(&format!("{}focus_dist = 0", camera)));
+        assert_eq!("test.toml: camera.aperture: can't be negative", error(&format!("{}aperture = -1", camera)));
+
+        let sphere = "[[shapes]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\n";
+        assert_eq!("test.toml: shapes[0].material: unknown material `gold`",
+                   error(&format!("{}{}radius = 1\nmaterial = \"gold\"", camera, sphere)));
+        assert!(error(&format!("{}{}radiuss = 1\nmaterial = \"gold\"", camera, sphere))
+            .starts_with("test.toml: shapes[0].radiuss: unknown key"));
+        assert_eq!("test.toml: shapes[0].material.albedo: expected a color, a texture name or a texture table, found a boolean",
+                   error(&format!("{}{}radius = 1\nmaterial = {{ type = \"lambertian\", albedo = true }}", camera, sphere)));
+
+        // unused materials are checked too, and can't refer to themselves
+        assert_eq!("test.toml: materials.a.base: material `a` refers to itself",
+                   error(&format!("{}[materials]\na = {{ type = \"bump\", base = \"a\", height = [1, 1, 1] }}", camera)));
+        assert_eq!("test.toml: textures.t.type: unknown type `plaid`, expected one of solid, checker, uv_checker, image, noise",
+                   error(&format!("{}[textures]\nt = {{ type = \"plaid\" }}", camera)));
+        assert_eq!("test.toml: materials.m.metal: unknown value `tin`, expected one of gold, copper, aluminium, silver",
+                   error(&format!("{}[materials]\nm = {{ type = \"conductor\", metal = \"tin\" }}", camera)));
+        assert_eq!("test.toml: materials.m.k: missing",
+                   error(&format!("{}[materials]\nm = {{ type = \"conductor\", eta = [1, 1, 1] }}", camera)));
+        assert_eq!("test.toml: materials.m.sheen: must be between 0 and 1",
+                   error(&format!("{}[materials]\nm = {{ type = \"principled\", sheen = 1.5 }}", camera)));
+        assert_eq!("test.toml: materials.m.absorption_color: must be above 0 and at most 1",
+                   error(&format!("{}[materials]\nm = {{ type = \"dielectric\", absorption_color = [0.5, 0, 0.5] }}", camera)));
+        assert_eq!("test.toml: materials.m.sellmeier: can't be combined with ior",
+                   error(&format!("{}[materials]\nm = {{ type = \"dielectric\", ior = 1.5, sellmeier = {{ b = [1, 0, 0], c = [0, 0, 0] }} }}", camera)));
+        assert_eq!("test.toml: materials.m.cauchy.b: missing",
+                   error(&format!("{}[materials]\nm = {{ type = \"dielectric\", cauchy = {{ a = 1.5 }} }}", camera)));
+        assert_eq!("test.toml: materials.m.roughness: must be between 0 and 1",
+                   error(&format!("{}[materials]\nm = {{ type = \"dielectric\", roughness = 2 }}", camera)));
+
+        assert_eq!("test.toml: fog.anisotropy: must be between -1 and 1",
+                   error(&format!("{}[fog]\ndensity = 0.1\nanisotropy = 1", camera)));
+        assert_eq!("test.toml: fog.distance: must be positive",
+                   error(&format!("{}[fog]\ndensity = 0.1\ndistance = 0", camera)));
+        assert_eq!("test.toml: shapes[0].medium.density: missing",
+                   error(&format!("{}{}radius = 1\nmedium = {{ albedo = [1, 1, 1] }}", camera, sphere)));
+        assert!(error(&format!("{}[[volumes]]\nfile = \"cloud.raw\"\nmin = [0, 0, 0]\nmax = [1, 1, 1]", camera))
+            .starts_with("test.toml: volumes[0].file: size of cloud.raw is unknown"));
+        assert_eq!("test.toml: lights[0].transform: lights can't be transformed or moved",
+                   error(&format!("{}[[lights]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\ncolor = [1, 1, 1]\ntransform = {{ scale = 2 }}", camera)));
+        assert_eq!("test.toml: shapes[0].transform.scale: can't be zero",
+                   error(&format!("{}{}radius = 1\nmaterial = {{ type = \"lambertian\", albedo = [1, 1, 1] }}\ntransform = {{ scale = [1, 0, 1] }}", camera, sphere)));
+    }
+    #[test]
+    fn empty_image_test() {
+        let folder = std::env::temp_dir().join(format!("scene_empty_image_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join("empty.hdr"), "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 0 +X 4\n").unwrap();
+        let camera = "[camera]\nlookfrom = [0, 0, 1]\nlookat = [0, 0, 0]\n";
+        let error = |source: String| match Scene::parse(&source, "test.toml", &folder) {
+            Ok(_) => panic!("no error for {}", source),
+            Err(e) => e.to_string(),
+        };
+
+        assert_eq!("test.toml: background.file: empty.hdr: empty hdr image",
+                   error(format!("{}[background]\ntype = \"environment\"\nfile = \"empty.hdr\"", camera)));
+        assert_eq!("test.toml: textures.t.file: empty.hdr: empty hdr image",
+                   error(format!("{}[textures]\nt = {{ type = \"image\", file = \"empty.hdr\" }}", camera)));
+        std::fs::remove_dir_all(&folder).unwrap();
+    }
+    #[test]
+    fn transform_test() {
+        // a unit quad in the xy plane, turned to face up and lifted
+        let source = "[camera]\nlookfrom = [0, 5, 0]\nlookat = [0, 0, 0]\nvup = [0, 0, -1]\n\
+                      [[shapes]]\ntype = \"quad\"\ncorner = [-1, -1, 0]\nedge_u = [2, 0, 0]\nedge_v = [0, 2, 0]\n\
+                      material = { type = \"lambertian\", albedo = [1, 1, 1] }\ntransform = { scale = 2, rotate = [-90, 0, 0], translate = [0, 1, 0] }";
+        let mut scene = Scene::parse(source, "test.toml", Path::new("")).unwrap_or_else(|e| panic!("{}", e));
+        scene.world.build_bvh();
+
+        let down = Ray::new(Vector3::new(1.5, 5.0, -1.5), Vector3::new(0.0, -1.0, 0.0));
+        let rec = scene.world.hit(&down, 0.001, f64::INFINITY).unwrap();
+        assert!((rec.t - 4.0).abs() < 1e-9);
+        assert!((rec.normal - Vector3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+
+        // moved out of the way by the end of the shutter
+        let moving = format!("{}\nmotion = [10, 0, 0]", source.replace(", translate = [0, 1, 0]", ""));
+        let mut scene = Scene::parse(&moving, "test.toml", Path::new("")).unwrap_or_else(|e| panic!("{}", e));
+        scene.world.build_bvh();
+        assert!(scene.world.hit(&down, 0.001, f64::INFINITY).is_some());
+        assert!(scene.world.hit(&Ray::with_time(down.origin, down.direction, 1.0), 0.001, f64::INFINITY).is_none());
+        assert_eq!("test.toml: shapes[0].motion: can't be combined with end_transform",
+                   error(&format!("{}\nend_transform = {{ scale = 2 }}", moving)));
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::OnceLock;
+
+use crate::color::{cie_xyz, RGBColor};
+
+// range of visible wavelengths sampled, in nanometers
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+const LAMBDA_RANGE: f64 = LAMBDA_MAX - LAMBDA_MIN;
+
+// edges of the smooth blue, green and red spectra colors are made of, and their softness
+const BLUE_EDGE: f64 = 490.0;
+const RED_EDGE: f64 = 585.0;
+const SOFTNESS: f64 = 10.0;
+
+// conversions between colors and spectra, computed once
+struct Tables {
+    // integral of the rgb response of the camera over the wavelengths, so a flat spectrum is white
+    white: [f64; 3],
+    // from the linear rgb of a color to the weights of the blue, green and red basis spectra
+    to_basis: [[f64; 3]; 3],
+}
+
+fn logistic(x: f64) -> f64 {
+    1.0 / (1.0 + f64::exp(-x))
+}
+
+// the blue, green and red basis spectra, which add up to one everywhere
+fn basis(lambda: f64) -> [f64; 3] {
+    let blue = 1.0 - logistic((lambda - BLUE_EDGE) / SOFTNESS);
+    let red = logistic((lambda - RED_EDGE) / SOFTNESS);
+    [blue, 1.0 - blue - red, red]
+}
+
+// linear rgb of light of a single wavelength, before normalizing to white
+fn response(lambda: f64) -> [f64; 3] {
+    let [x, y, z] = cie_xyz(lambda);
+    let rgb = RGBColor::from_xyz(x, y, z);
+    [rgb.r(), rgb.g(), rgb.b()]
+}
+
+fn invert(m: [[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    let cofactor = |r: usize, c: usize| {
+        let (r1, r2) = ((r + 1) % 3, (r + 2) % 3);
+        let (c1, c2) = ((c + 1) % 3, (c + 2) % 3);
+        m[r1][c1] * m[r2][c2] - m[r1][c2] * m[r2][c1]
+    };
+    let det = m[0][0] * cofactor(0, 0) + m[0][1] * cofactor(0, 1) + m[0][2] * cofactor(0, 2);
+    let mut inverse = [[0.0; 3]; 3];
+    for (r, row) in inverse.iter_mut().enumerate() {
+        for (c, value) in row.iter_mut().enumerate() {
+            *value = cofactor(c, r) / det;
+        }
+    }
+    inverse
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(|| {
+        // integrated in steps of 1 nm
+        let lambdas = || (0..LAMBDA_RANGE as usize).map(|i| LAMBDA_MIN + i as f64 + 0.5);
+        let mut white = [0.0; 3];
+        for lambda in lambdas() {
+            let rgb = response(lambda);
+            for k in 0..3 {
+                white[k] += rgb[k];
+            }
+        }
+        // rgb of each basis spectrum, which upsampling inverts
+        let mut to_rgb = [[0.0; 3]; 3];
+        for lambda in lambdas() {
+            let (rgb, basis) = (response(lambda), basis(lambda));
+            for k in 0..3 {
+                for j in 0..3 {
+                    to_rgb[k][j] += rgb[k] / white[k] * basis[j];
+                }
+            }
+        }
+        Tables {white, to_basis: invert(to_rgb)}
+    })
+}
+
+// wavelengths a camera sample is traced at, in nanometers: a hero wavelength picked at random
+// and two more evenly spaced after it. Their radiance is carried in the three channels of
+// `RGBColor`s until converted back by `to_rgb`
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct Wavelengths {
+    pub lambda: [f64; 3],
+    // only the hero wavelength is left, the others having gone other ways through dispersion
+    pub single: bool,
+}
+
+impl Wavelengths {
+    // for `u` uniform in [0, 1)
+    pub fn sample(u: f64) -> Self {
+        let hero = LAMBDA_MIN + u * LAMBDA_RANGE;
+        let lambda = [0.0, 1.0, 2.0].map(|i| LAMBDA_MIN + (hero - LAMBDA_MIN + i * LAMBDA_RANGE / 3.0) % LAMBDA_RANGE);
+        Wavelengths {lambda, single: false}
+    }
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+    pub fn dispersed(&self) -> Self {
+        Wavelengths {lambda: self.lambda, single: true}
+    }
+    // values at the wavelengths of a smooth spectrum with the color, which is 1 everywhere
+    // for white
+    pub fn upsample(&self, color: RGBColor) -> RGBColor {
+        let m = tables().to_basis;
+        let rgb = [color.r(), color.g(), color.b()];
+        let weights = [0, 1, 2].map(|j| m[j][0] * rgb[0] + m[j][1] * rgb[1] + m[j][2] * rgb[2]);
+        let value = |lambda: f64| {
+            let basis = basis(lambda);
+            f64::max(0.0, weights[0] * basis[0] + weights[1] * basis[1] + weights[2] * basis[2])
+        };
+        RGBColor::new(value(self.lambda[0]), value(self.lambda[1]), value(self.lambda[2]))
+    }
+    // the rgb color of radiance at the wavelengths, through the CIE XYZ matching functions.
+    // Averaged over camera samples it converges to the color of the whole spectrum
+    pub fn to_rgb(&self, radiance: RGBColor) -> RGBColor {
+        let white = tables().white;
+        let values = [radiance.r(), radiance.g(), radiance.b()];
+        let mut rgb = [0.0; 3];
+        for (lambda, value) in self.lambda.iter().zip(values) {
+            let response = response(*lambda);
+            for k in 0..3 {
+                // divided by the density of the wavelength, and averaged over the three of them
+                rgb[k] += value * response[k] / white[k] * LAMBDA_RANGE / 3.0;
+            }
+        }
+        RGBColor::new(rgb[0], rgb[1], rgb[2])
+    }
+}
+
+// index of refraction of a dielectric, varying with the wavelength for dispersion. Wavelengths
+// are in micrometers in the formulas
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Ior {
+    Constant(f64),
+    // a + b / λ²
+    Cauchy(f64, f64),
+    // square root of 1 + Σ b λ² / (λ² - c)
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl From<f64> for Ior {
+    fn from(ior: f64) -> Self {
+        Ior::Constant(ior)
+    }
+}
+
+impl Ior {
+    // Schott's N-BK7 crown glass
+    pub const BK7: Ior = Ior::Sellmeier([1.03961212, 0.231792344, 1.01046945], [0.00600069867, 0.0200179144, 103.560653]);
+    pub const DIAMOND: Ior = Ior::Sellmeier([4.3356, 0.3306, 0.0], [0.011236, 0.030625, 0.0]);
+
+    // at a wavelength in nanometers
+    pub fn at(&self, lambda: f64) -> f64 {
+        let l2 = (lambda / 1000.0) * (lambda / 1000.0);
+        match self {
+            Ior::Constant(ior) => *ior,
+            Ior::Cauchy(a, b) => a + b / l2,
+            Ior::Sellmeier(b, c) => f64::sqrt(1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>()),
+        }
+    }
+    // at the yellow sodium line, the index quoted for glasses and used when rendering in rgb
+    pub fn mean(&self) -> f64 {
+        self.at(587.6)
+    }
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Ior::Constant(_))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Ior, Wavelengths};
+    use crate::color::RGBColor;
+
+    #[test]
+    fn spectrum_test() {
+        // white is flat, and upsampled colors come back from their spectra
+        let n = 3000;
+        for color in [RGBColor::new(1.0, 1.0, 1.0), RGBColor::new(0.7, 0.4, 0.2), RGBColor::new(0.2, 0.3, 0.6)] {
+            let mut sum = RGBColor::new(0.0, 0.0, 0.0);
+            for i in 0..n {
+                let wavelengths = Wavelengths::sample((i as f64 + 0.5) / n as f64);
+                let values = wavelengths.upsample(color);
+                if color == RGBColor::new(1.0, 1.0, 1.0) {
+                    assert!((values.r() - 1.0).abs() < 1e-9 && (values.b() - 1.0).abs() < 1e-9);
+                }
+                sum = sum + wavelengths.to_rgb(values) * (1.0 / n as f64);
+            }
+            for (a, b) in [(sum.r(), color.r()), (sum.g(), color.g()), (sum.b(), color.b())] {
+                assert!((a - b).abs() < 1e-2, "{:?} {:?}", sum, color);
+            }
+        }
+
+        // the secondary wavelengths wrap around the range
+        let wavelengths = Wavelengths::sample(0.9);
+        assert!((wavelengths.lambda[1] - (740.0 + 133.333 - 400.0)).abs() < 1e-2);
+    }
+    #[test]
+    fn ior_test() {
+        assert!((Ior::BK7.mean() - 1.5168).abs() < 1e-4);
+        assert!((Ior::DIAMOND.mean() - 2.417).abs() < 2e-3);
+        // blue light bends more than red
+        for ior in [Ior::BK7, Ior::DIAMOND, Ior::Cauchy(1.5, 0.004)] {
+            assert!(ior.at(450.0) > ior.at(650.0));
+        }
+        assert_eq!(1.33, Ior::from(1.33).at(400.0));
+    }
+}
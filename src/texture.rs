@@ -0,0 +1,257 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use fastrand::Rng;
+
+use crate::{color::RGBColor, framebuffer::Framebuffer, image_reader::load_image, noise::Noise, utils::clamp, vector3::Vector3};
+
+// color that varies over a surface, looked up with the surface coordinates and the position of a hit
+#[derive(Clone)]
+pub enum Texture {
+    Solid(RGBColor),
+    // alternates between two textures in cubes of `scale` world units
+    Checker {scale: f64, even: Box<Texture>, odd: Box<Texture>},
+    // alternates between two textures in a grid over the [0, 1] square of surface coordinates
+    UvChecker {columns: f64, rows: f64, even: Box<Texture>, odd: Box<Texture>},
+    Image(Arc<ImageTexture>),
+    Noise(Arc<NoiseTexture>),
+}
+
+impl From<RGBColor> for Texture {
+    fn from(color: RGBColor) -> Self {
+        Texture::Solid(color)
+    }
+}
+
+impl Texture {
+    pub fn value(&self, u: f64, v: f64, point: Vector3) -> RGBColor {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker {scale, even, odd} => {
+                let cell = |x: f64| (x / scale).floor() as i64;
+                match (cell(point.x) + cell(point.y) + cell(point.z)).rem_euclid(2) == 0 {
+                    true => even.value(u, v, point),
+                    false => odd.value(u, v, point),
+                }
+            }
+            Texture::UvChecker {columns, rows, even, odd} => {
+                let cell = (u * columns).floor() as i64 + (v * rows).floor() as i64;
+                match cell.rem_euclid(2) == 0 {
+                    true => even.value(u, v, point),
+                    false => odd.value(u, v, point),
+                }
+            }
+            Texture::Image(image) => image.value(u, v),
+            Texture::Noise(noise) => noise.value(u, v, point),
+        }
+    }
+}
+
+// how image lookups outside of the [0, 1] square of surface coordinates behave
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum WrapMode {
+    Repeat,
+    // keeps the color of the nearest edge
+    Clamp,
+    // repeats, flipping every other copy
+    Mirror,
+}
+
+impl WrapMode {
+    // maps a pixel index to one inside an image of `size` pixels
+    fn wrap(&self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Clamp => i.clamp(0, size - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * size);
+                match m < size {
+                    true => m,
+                    false => 2 * size - 1 - m,
+                }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+// image covering the [0, 1] square of surface coordinates once, with v going up
+pub struct ImageTexture {
+    image: Framebuffer,
+    pub wrap: WrapMode,
+    pub filter: Filter,
+}
+
+impl ImageTexture {
+    // repeating and bilinearly filtered
+    pub fn new(image: Framebuffer) -> Self {
+        assert!(image.width() > 0 && image.height() > 0, "Texture image is empty");
+        ImageTexture {image, wrap: WrapMode::Repeat, filter: Filter::Bilinear}
+    }
+    // see `load_image` for the meaning of `srgb`
+    pub fn load(path: &Path, srgb: bool) -> io::Result<Self> {
+        let image = load_image(path, srgb)?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is an empty image", path.display())))
+        }
+        Ok(ImageTexture::new(image))
+    }
+
+    pub fn value(&self, u: f64, v: f64) -> RGBColor {
+        let (width, height) = (self.image.width(), self.image.height());
+        let x = u * width as f64;
+        let y = (1.0 - v) * height as f64;
+
+        match self.filter {
+            Filter::Nearest => self.image.get(
+                self.wrap.wrap(x.floor() as i64, width),
+                self.wrap.wrap(y.floor() as i64, height),
+            ),
+            Filter::Bilinear => {
+                // between the centers of the four nearest pixels
+                let (x, y) = (x - 0.5, y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (xa, xb) = (self.wrap.wrap(x0 as i64, width), self.wrap.wrap(x0 as i64 + 1, width));
+                let (ya, yb) = (self.wrap.wrap(y0 as i64, height), self.wrap.wrap(y0 as i64 + 1, height));
+
+                let top = self.image.get(xa, ya) * (1.0 - fx) + self.image.get(xb, ya) * fx;
+                let bottom = self.image.get(xa, yb) * (1.0 - fx) + self.image.get(xb, yb) * fx;
+                top * (1.0 - fy) + bottom * fy
+            }
+        }
+    }
+}
+
+// scalar patterns made of noise, in [0, 1]
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub enum NoisePattern {
+    Perlin,
+    // with the number of octaves
+    Turbulence(u32),
+    Fbm(u32),
+    Worley,
+    // veins following a sine wave along z, disturbed by turbulence with the number of octaves
+    Marble(u32),
+    // rings around the y axis, disturbed by noise
+    Wood,
+}
+
+// blend between two textures following a noise pattern of the hit position
+pub struct NoiseTexture {
+    noise: Noise,
+    pub pattern: NoisePattern,
+    // frequency of the pattern, in features per world unit
+    pub scale: f64,
+    pub low: Texture,
+    pub high: Texture,
+}
+
+impl NoiseTexture {
+    pub fn new(rng: &Rng, pattern: NoisePattern, scale: f64, low: Texture, high: Texture) -> Self {
+        NoiseTexture {noise: Noise::new(rng), pattern, scale, low, high}
+    }
+
+    pub fn pattern_value(&self, point: Vector3) -> f64 {
+        let p = point * self.scale;
+        let value = match self.pattern {
+            NoisePattern::Perlin => 0.5 * (self.noise.perlin(p) + 1.0),
+            NoisePattern::Turbulence(octaves) => self.noise.turbulence(p, octaves),
+            NoisePattern::Fbm(octaves) => 0.5 * (self.noise.fbm(p, octaves, 2.0, 0.5) + 1.0),
+            NoisePattern::Worley => self.noise.worley(p),
+            NoisePattern::Marble(octaves) => 0.5 * (1.0 + f64::sin(p.z + 10.0 * self.noise.turbulence(p, octaves))),
+            NoisePattern::Wood => {
+                let rings = f64::sqrt(p.x * p.x + p.z * p.z) + 0.5 * self.noise.perlin(p);
+                rings - rings.floor()
+            }
+        };
+        clamp(value, 0.0, 1.0)
+    }
+
+    pub fn value(&self, u: f64, v: f64, point: Vector3) -> RGBColor {
+        let t = self.pattern_value(point);
+        self.low.value(u, v, point) * (1.0 - t) + self.high.value(u, v, point) * t
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{Filter, ImageTexture, NoisePattern, NoiseTexture, Texture, WrapMode};
+    use crate::{color::RGBColor, framebuffer::Framebuffer, vector3::Vector3};
+
+    #[test]
+    fn checker_test() {
+        let (black, white) = (RGBColor::new(0.0, 0.0, 0.0), RGBColor::new(1.0, 1.0, 1.0));
+        let checker = Texture::Checker {scale: 2.0, even: Box::new(black.into()), odd: Box::new(white.into())};
+
+        assert_eq!(black, checker.value(0.0, 0.0, Vector3::new(0.5, 0.5, 0.5)));
+        assert_eq!(white, checker.value(0.0, 0.0, Vector3::new(2.5, 0.5, 0.5)));
+        assert_eq!(white, checker.value(0.0, 0.0, Vector3::new(-0.5, 0.5, 0.5)));
+
+        let uv = Texture::UvChecker {columns: 4.0, rows: 2.0, even: Box::new(black.into()), odd: Box::new(white.into())};
+        assert_eq!(black, uv.value(0.1, 0.1, Vector3::new(0.0, 0.0, 0.0)));
+        assert_eq!(white, uv.value(0.3, 0.1, Vector3::new(0.0, 0.0, 0.0)));
+        assert_eq!(white, uv.value(0.1, 0.6, Vector3::new(0.0, 0.0, 0.0)));
+    }
+    #[test]
+    fn image_test() {
+        let mut image = Framebuffer::new(2, 2);
+        image.set(0, 0, RGBColor::new(1.0, 0.0, 0.0));
+        image.set(1, 1, RGBColor::new(0.0, 0.0, 1.0));
+        let texture = Texture::Image(Arc::new(ImageTexture::new(image)));
+        let origin = Vector3::new(0.0, 0.0, 0.0);
+
+        // the top left pixel is at high v, and the image repeats
+        assert_eq!(RGBColor::new(1.0, 0.0, 0.0), texture.value(0.25, 0.75, origin));
+        assert_eq!(RGBColor::new(0.0, 0.0, 1.0), texture.value(0.75, 0.25, origin));
+        assert_eq!(RGBColor::new(0.0, 0.0, 1.0), texture.value(-0.25, 1.25, origin));
+    }
+    #[test]
+    fn wrap_filter_test() {
+        // black and white columns
+        let mut image = Framebuffer::new(2, 1);
+        image.set(1, 0, RGBColor::new(1.0, 1.0, 1.0));
+        let mut texture = ImageTexture::new(image);
+
+        // halfway between the two pixel centers, and between the last one and the first repeated one
+        assert_eq!(RGBColor::new(0.5, 0.5, 0.5), texture.value(0.5, 0.5));
+        assert_eq!(RGBColor::new(0.5, 0.5, 0.5), texture.value(1.0, 0.5));
+
+        texture.wrap = WrapMode::Clamp;
+        assert_eq!(RGBColor::new(1.0, 1.0, 1.0), texture.value(1.0, 0.5));
+        assert_eq!(RGBColor::new(0.0, 0.0, 0.0), texture.value(-3.0, 0.5));
+
+        texture.wrap = WrapMode::Mirror;
+        texture.filter = Filter::Nearest;
+        assert_eq!(RGBColor::new(1.0, 1.0, 1.0), texture.value(1.1, 0.5));
+        assert_eq!(RGBColor::new(0.0, 0.0, 0.0), texture.value(1.6, 0.5));
+        assert_eq!(RGBColor::new(0.0, 0.0, 0.0), texture.value(2.1, 0.5));
+    }
+    #[test]
+    fn noise_texture_test() {
+        let (black, white) = (RGBColor::new(0.0, 0.0, 0.0), RGBColor::new(1.0, 1.0, 1.0));
+        let rng = fastrand::Rng::with_seed(8);
+        let patterns = [NoisePattern::Perlin, NoisePattern::Turbulence(4), NoisePattern::Fbm(4),
+                        NoisePattern::Worley, NoisePattern::Marble(4), NoisePattern::Wood];
+
+        for pattern in patterns {
+            let texture = NoiseTexture::new(&rng, pattern, 3.0, black.into(), white.into());
+            let values: Vec<f64> = (0..200)
+                .map(|i| texture.value(0.0, 0.0, Vector3::new(i as f64 * 0.037, 0.5, i as f64 * -0.021)).r())
+                .collect();
+            // gray levels between the two colors, and not all the same
+            assert!(values.iter().all(|v| (0.0..=1.0).contains(v)));
+            assert!(values.iter().any(|v| (v - values[0]).abs() > 0.05), "{:?}", pattern);
+        }
+    }
+}
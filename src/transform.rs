@@ -0,0 +1,411 @@
+use std::ops::Mul;
+
+use crate::{aabb::Aabb, vector3::Vector3};
+
+pub type Matrix4 = [[f64; 4]; 4];
+
+const IDENTITY: Matrix4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn multiply(a: &Matrix4, b: &Matrix4) -> Matrix4 {
+    let mut m = [[0.0; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, cell) in row.iter_mut().enumerate() {
+            *cell = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+// gauss-jordan elimination with partial pivoting, None for singular matrices
+fn invert(matrix: &Matrix4) -> Option<Matrix4> {
+    let mut a = *matrix;
+    let mut inv = IDENTITY;
+    for col in 0..4 {
+        let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None
+        }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+
+        let scale = 1.0 / a[col][col];
+        for j in 0..4 {
+            a[col][j] *= scale;
+            inv[col][j] *= scale;
+        }
+        for row in 0..4 {
+            let factor = a[row][col];
+            if row == col || factor == 0.0 {
+                continue
+            }
+            for j in 0..4 {
+                a[row][j] -= factor * a[col][j];
+                inv[row][j] -= factor * inv[col][j];
+            }
+        }
+    }
+    Some(inv)
+}
+
+// affine transform of 3d space, kept together with its inverse. Products apply the right
+// hand side first, so `translate * rotate` rotates about the origin and then moves
+#[derive(Debug,PartialEq,Clone,Copy)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {matrix: IDENTITY, inverse: IDENTITY}
+    }
+    // row-major, with the translation in the last column. None if it can't be inverted
+    pub fn from_matrix(matrix: Matrix4) -> Option<Self> {
+        Some(Transform {matrix, inverse: invert(&matrix)?})
+    }
+    pub fn translate(offset: Vector3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for i in 0..3 {
+            matrix[i][3] = offset[i];
+            inverse[i][3] = -offset[i];
+        }
+        Transform {matrix, inverse}
+    }
+    // factors can be negative to mirror, but not zero
+    pub fn scale(factors: Vector3) -> Self {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for i in 0..3 {
+            matrix[i][i] = factors[i];
+            inverse[i][i] = 1.0 / factors[i];
+        }
+        Transform {matrix, inverse}
+    }
+    // counter-clockwise when looking down the axis towards the origin
+    pub fn rotate(axis: Vector3, degrees: f64) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let k = 1.0 - cos;
+        let mut matrix = IDENTITY;
+        matrix[0][..3].copy_from_slice(&[cos + a.x*a.x*k, a.x*a.y*k - a.z*sin, a.x*a.z*k + a.y*sin]);
+        matrix[1][..3].copy_from_slice(&[a.y*a.x*k + a.z*sin, cos + a.y*a.y*k, a.y*a.z*k - a.x*sin]);
+        matrix[2][..3].copy_from_slice(&[a.z*a.x*k - a.y*sin, a.z*a.y*k + a.x*sin, cos + a.z*a.z*k]);
+
+        // rotations are orthogonal, the inverse is the transpose
+        let mut inverse = IDENTITY;
+        for (i, row) in inverse.iter_mut().enumerate().take(3) {
+            for (j, cell) in row.iter_mut().enumerate().take(3) {
+                *cell = matrix[j][i];
+            }
+        }
+        Transform {matrix, inverse}
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+    pub fn inverse(&self) -> Transform {
+        Transform {matrix: self.inverse, inverse: self.matrix}
+    }
+
+    pub fn point(&self, p: Vector3) -> Vector3 {
+        let m = &self.matrix;
+        Vector3::new(
+            m[0][0]*p.x + m[0][1]*p.y + m[0][2]*p.z + m[0][3],
+            m[1][0]*p.x + m[1][1]*p.y + m[1][2]*p.z + m[1][3],
+            m[2][0]*p.x + m[2][1]*p.y + m[2][2]*p.z + m[2][3],
+        )
+    }
+    // directions ignore the translation
+    pub fn vector(&self, v: Vector3) -> Vector3 {
+        let m = &self.matrix;
+        Vector3::new(
+            m[0][0]*v.x + m[0][1]*v.y + m[0][2]*v.z,
+            m[1][0]*v.x + m[1][1]*v.y + m[1][2]*v.z,
+            m[2][0]*v.x + m[2][1]*v.y + m[2][2]*v.z,
+        )
+    }
+    // normals go through the inverse transpose to stay perpendicular to the transformed surface.
+    // The result isn't unit length
+    pub fn normal(&self, n: Vector3) -> Vector3 {
+        let m = &self.inverse;
+        Vector3::new(
+            m[0][0]*n.x + m[1][0]*n.y + m[2][0]*n.z,
+            m[0][1]*n.x + m[1][1]*n.y + m[2][1]*n.z,
+            m[0][2]*n.x + m[1][2]*n.y + m[2][2]*n.z,
+        )
+    }
+    // box around the eight transformed corners. Infinite boxes stay infinite
+    pub fn bounding_box(&self, b: &Aabb) -> Aabb {
+        if !b.is_finite() {
+            return *b
+        }
+        let mut bounds = Aabb::empty();
+        for corner in 0..8 {
+            let pick = |bit: usize, min: f64, max: f64| if corner & bit == 0 { min } else { max };
+            let p = Vector3::new(pick(1, b.min.x, b.max.x), pick(2, b.min.y, b.max.y), pick(4, b.min.z, b.max.z));
+            bounds = bounds.grow(self.point(p));
+        }
+        bounds
+    }
+}
+
+impl Mul<Transform> for Transform {
+    type Output = Transform;
+    fn mul(self, rhs: Transform) -> Self::Output {
+        Transform {
+            matrix: multiply(&self.matrix, &rhs.matrix),
+            inverse: multiply(&rhs.inverse, &self.inverse),
+        }
+    }
+}
+
+// rotation as a unit quaternion, to interpolate between orientations
+#[derive(Debug,Clone,Copy)]
+struct Quaternion {
+    w: f64,
+    v: Vector3,
+}
+
+impl Quaternion {
+    // from the upper 3x3 of `m`, which must be a rotation
+    fn from_matrix(m: &Matrix4) -> Self {
+        let trace = m[0][0] + m[1][1] + m[2][2];
+        // divides by the largest component to stay accurate
+        let (w, x, y, z) = if trace > 0.0 {
+            let s = 2.0 * f64::sqrt(trace + 1.0);
+            (0.25 * s, (m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s)
+        } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+            let s = 2.0 * f64::sqrt(1.0 + m[0][0] - m[1][1] - m[2][2]);
+            ((m[2][1] - m[1][2]) / s, 0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s)
+        } else if m[1][1] > m[2][2] {
+            let s = 2.0 * f64::sqrt(1.0 + m[1][1] - m[0][0] - m[2][2]);
+            ((m[0][2] - m[2][0]) / s, (m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s)
+        } else {
+            let s = 2.0 * f64::sqrt(1.0 + m[2][2] - m[0][0] - m[1][1]);
+            ((m[1][0] - m[0][1]) / s, (m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s)
+        };
+        Quaternion {w, v: Vector3::new(x, y, z)}
+    }
+
+    fn to_matrix(self) -> Matrix4 {
+        let (w, Vector3 {x, y, z}) = (self.w, self.v);
+        let mut m = IDENTITY;
+        m[0][..3].copy_from_slice(&[1.0 - 2.0*(y*y + z*z), 2.0*(x*y - w*z), 2.0*(x*z + w*y)]);
+        m[1][..3].copy_from_slice(&[2.0*(x*y + w*z), 1.0 - 2.0*(x*x + z*z), 2.0*(y*z - w*x)]);
+        m[2][..3].copy_from_slice(&[2.0*(x*z - w*y), 2.0*(y*z + w*x), 1.0 - 2.0*(x*x + y*y)]);
+        m
+    }
+
+    // spherical interpolation along the shortest way
+    fn slerp(self, other: Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.w * other.w + self.v.dot(other.v);
+        let mut other = other;
+        // q and -q are the same rotation
+        if cos < 0.0 {
+            (other.w, other.v, cos) = (-other.w, -other.v, -cos);
+        }
+        let (a, b) = match cos > 0.9995 {
+            // nearly the same, where the sines below lose precision
+            true => (1.0 - t, t),
+            false => {
+                let theta = cos.acos();
+                (f64::sin((1.0 - t) * theta) / theta.sin(), f64::sin(t * theta) / theta.sin())
+            }
+        };
+        let (w, v) = (self.w * a + other.w * b, self.v * a + other.v * b);
+        let length = f64::sqrt(w * w + v.length_squared());
+        Quaternion {w: w / length, v: v / length}
+    }
+}
+
+fn determinant3(m: &Matrix4) -> f64 {
+    m[0][0] * (m[1][1]*m[2][2] - m[1][2]*m[2][1])
+        - m[0][1] * (m[1][0]*m[2][2] - m[1][2]*m[2][0])
+        + m[0][2] * (m[1][0]*m[2][1] - m[1][1]*m[2][0])
+}
+
+// splits a transform into a translation, a rotation and a stretch, the symmetric rest holding
+// the scaling and shearing, so that it is translation * rotation * stretch
+fn decompose(m: &Matrix4) -> (Vector3, Quaternion, Matrix4) {
+    let translation = Vector3::new(m[0][3], m[1][3], m[2][3]);
+    let mut linear = *m;
+    for row in linear.iter_mut().take(3) {
+        row[3] = 0.0;
+    }
+
+    // polar decomposition: averaging the matrix with its inverse transpose converges to the
+    // closest orthogonal matrix
+    let mut rotation = linear;
+    for _ in 0..100 {
+        let inverse = match invert(&rotation) {
+            Some(inverse) => inverse,
+            None => break,
+        };
+        let mut next = IDENTITY;
+        let mut change: f64 = 0.0;
+        for i in 0..3 {
+            for j in 0..3 {
+                next[i][j] = 0.5 * (rotation[i][j] + inverse[j][i]);
+                change = change.max((next[i][j] - rotation[i][j]).abs());
+            }
+        }
+        rotation = next;
+        if change < 1e-12 {
+            break
+        }
+    }
+    // a mirroring transform gives a reflection, negating it leaves a rotation and moves the
+    // mirroring into the stretch
+    if determinant3(&rotation) < 0.0 {
+        for row in rotation.iter_mut().take(3) {
+            for cell in row.iter_mut().take(3) {
+                *cell = -*cell;
+            }
+        }
+    }
+
+    let mut transposed = IDENTITY;
+    for i in 0..3 {
+        for j in 0..3 {
+            transposed[i][j] = rotation[j][i];
+        }
+    }
+    (translation, Quaternion::from_matrix(&rotation), multiply(&transposed, &linear))
+}
+
+// samples taken over the motion to bound it
+const MOTION_STEPS: usize = 64;
+
+// transform moving from `start` at time 0 to `end` at time 1. The translations, rotations and
+// stretches of both are interpolated separately, so a spinning shape keeps its size halfway
+#[derive(Debug,Clone,Copy)]
+pub struct AnimatedTransform {
+    start: Transform,
+    end: Transform,
+    translations: [Vector3; 2],
+    rotations: [Quaternion; 2],
+    stretches: [Matrix4; 2],
+}
+
+impl AnimatedTransform {
+    pub fn new(start: Transform, end: Transform) -> Self {
+        let (t0, r0, s0) = decompose(&start.matrix);
+        let (t1, r1, s1) = decompose(&end.matrix);
+        AnimatedTransform {start, end, translations: [t0, t1], rotations: [r0, r1], stretches: [s0, s1]}
+    }
+
+    // held at the start and end outside of [0, 1]
+    pub fn at(&self, time: f64) -> Transform {
+        if time <= 0.0 {
+            return self.start
+        }
+        if time >= 1.0 {
+            return self.end
+        }
+        let [t0, t1] = self.translations;
+        let translation = Transform::translate(t0 * (1.0 - time) + t1 * time);
+        let rotation = self.rotations[0].slerp(self.rotations[1], time).to_matrix();
+        let mut stretch = IDENTITY;
+        for (i, row) in stretch.iter_mut().enumerate().take(3) {
+            for (j, cell) in row.iter_mut().enumerate().take(3) {
+                *cell = self.stretches[0][i][j] * (1.0 - time) + self.stretches[1][i][j] * time;
+            }
+        }
+        let matrix = multiply(&translation.matrix, &multiply(&rotation, &stretch));
+        Transform::from_matrix(matrix).unwrap_or(self.start)
+    }
+
+    // union of the boxes at evenly spaced times, widened for the arcs rotating points take
+    // between them
+    pub fn bounding_box(&self, b: &Aabb) -> Aabb {
+        if !b.is_finite() {
+            return *b
+        }
+        let mut bounds = Aabb::empty();
+        for i in 0..=MOTION_STEPS {
+            let time = i as f64 / MOTION_STEPS as f64;
+            let step = self.at(time).bounding_box(b);
+            let center = self.at(time).point(Vector3::new(0.0, 0.0, 0.0));
+            let reach = f64::max((step.min - center).length(), (step.max - center).length());
+            let pad = Vector3::new(reach, reach, reach) * 1e-3;
+            bounds = bounds.union(&Aabb::new(step.min - pad, step.max + pad));
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AnimatedTransform, Transform};
+    use crate::{aabb::Aabb, vector3::Vector3};
+
+    fn assert_near(a: Vector3, b: Vector3) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn transform_test() {
+        let p = Vector3::new(1.0, 2.0, 3.0);
+        let rotate = Transform::rotate(Vector3::new(0.0, 0.0, 1.0), 90.0);
+        assert_near(Vector3::new(-2.0, 1.0, 3.0), rotate.point(p));
+
+        // scaled first, then rotated, then moved
+        let t = Transform::translate(Vector3::new(0.0, 0.0, 5.0)) * rotate * Transform::scale(Vector3::new(2.0, 2.0, 2.0));
+        assert_near(Vector3::new(-4.0, 2.0, 11.0), t.point(p));
+        assert_near(Vector3::new(-4.0, 2.0, 6.0), t.vector(p));
+        assert_near(p, t.inverse().point(t.point(p)));
+
+        // the inverse computed from the matrix matches the one built along
+        let general = Transform::from_matrix(*t.matrix()).unwrap();
+        assert_near(t.inverse().point(p), general.inverse().point(p));
+        assert!(Transform::from_matrix([[0.0; 4]; 4]).is_none());
+
+        // normals stay perpendicular to the surface under non-uniform scaling
+        let squash = Transform::scale(Vector3::new(1.0, 4.0, 1.0));
+        let (tangent, normal) = (Vector3::new(1.0, -1.0, 0.0), Vector3::new(1.0, 1.0, 0.0));
+        assert!(squash.vector(tangent).dot(squash.normal(normal)).abs() < 1e-12);
+
+        let b = rotate.bounding_box(&Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 1.0, 1.0)));
+        assert_near(Vector3::new(-1.0, 0.0, 0.0), b.min);
+        assert_near(Vector3::new(0.0, 2.0, 1.0), b.max);
+    }
+    #[test]
+    fn animated_test() {
+        let p = Vector3::new(1.0, 0.0, 0.0);
+        let start = Transform::translate(Vector3::new(0.0, 2.0, 0.0));
+        let end = Transform::translate(Vector3::new(4.0, 2.0, 0.0)) * Transform::rotate(Vector3::new(0.0, 1.0, 0.0), 90.0)
+            * Transform::scale(Vector3::new(3.0, 3.0, 3.0));
+        let animated = AnimatedTransform::new(start, end);
+
+        assert_near(start.point(p), animated.at(-1.0).point(p));
+        assert_near(end.point(p), animated.at(1.0).point(p));
+        // halfway turned by 45 degrees and scaled by 2, rather than along the straight line
+        let half = Vector3::new(2.0, 2.0, 0.0) + Vector3::new(1.0, 0.0, -1.0) * f64::sqrt(2.0);
+        assert_near(half, animated.at(0.5).point(p));
+        assert_near(p, animated.at(0.5).inverse().point(half));
+
+        // mirrored keyframes still interpolate
+        let mirror = Transform::scale(Vector3::new(-1.0, 1.0, 1.0));
+        assert_near(Vector3::new(-1.0, 0.0, 0.0), AnimatedTransform::new(mirror, mirror).at(0.3).point(p));
+
+        let b = Aabb::new(Vector3::new(0.5, -0.5, -0.5), Vector3::new(1.5, 0.5, 0.5));
+        let bounds = animated.bounding_box(&b);
+        for i in 0..=100 {
+            let moved = animated.at(i as f64 / 100.0).point(Vector3::new(1.5, 0.5, 0.5));
+            assert!(bounds.min.x <= moved.x && moved.x <= bounds.max.x && bounds.min.z <= moved.z && moved.z <= bounds.max.z);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{vector3::Vector3, ray::Ray, hittable::{World, HitRecord, Hittable, Shape}, color::RGBColor, material::{LightReaction, Material}};
+use crate::{vector3::Vector3, ray::Ray, grid::{delta_track, ratio_track}, hittable::{HitRecord, World, Hittable, Shape}, color::RGBColor, material::{apply_bump, LightReaction, Material}, background::Background};
 use fastrand::Rng;
 
 pub fn clamp(x: f64, min: f64, max: f64) -> f64{
@@ -8,7 +8,7 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64{
     if x > max {
         return max
     }
-    return x
+    x
 }
 
 fn random_vec(min: f64, max: f64, rng: &Rng) -> Vector3 {
@@ -40,43 +40,162 @@ pub fn random_vec_in_unit_disk(rng: &Rng) -> Vector3 {
     }
 }
 
+// two unit vectors that make an orthonormal basis with the unit vector `n`
+// (branchless construction from Duff et al., "Building an Orthonormal Basis, Revisited")
+pub fn orthonormal_basis(n: Vector3) -> (Vector3, Vector3) {
+    let sign = f64::copysign(1.0, n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+
+    (
+        Vector3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        Vector3::new(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+// weight of a sample taken with density `pdf` when the same path could also have been
+// sampled with density `other` (Veach's power heuristic)
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other * other);
+    a / (a + b)
+}
+
+// light reaching the hit straight from one randomly picked light, weighted against finding
+// the same light by sampling the material
+fn sample_direct(world: &World, ray: &Ray, rec: &HitRecord, rng: &Rng) -> RGBColor {
+    let black = RGBColor::new(0.0, 0.0, 0.0);
+    let count = world.light_count();
+    if count == 0 {
+        return black
+    }
+    let light = world.light(rng.usize(..count));
+    let emitted = match light.material() {
+        Some(Material::DiffuseLight(color, strength)) => *color * *strength,
+        _ => return black,
+    };
+    let sample = match light.sample_light(rec.point, rng) {
+        Some(sample) if sample.pdf > 0.0 => sample,
+        _ => return black,
+    };
+
+    let to_light = sample.point - rec.point;
+    let distance = to_light.length();
+    let direction = to_light / distance;
+    // behind the light's emitting face
+    if direction.dot(sample.normal) >= 0.0 {
+        return black
+    }
+    let bsdf = rec.material.eval(ray, rec, direction);
+    if bsdf == black {
+        return black
+    }
+    if world.hit(&Ray::with_time(rec.point, direction, ray.time), 0.001, distance - 0.001).is_some() {
+        return black
+    }
+    let mut shadow = Ray::with_time(rec.point, direction, ray.time);
+    shadow.wavelengths = ray.wavelengths;
+    shadow.absorption = rec.material.absorption_after(ray, rec, direction);
+    let transmittance = world.fog.as_ref().map_or(1.0, |fog| fog.transmittance(distance))
+        * ratio_track(world.volumes(), &shadow, 0.001, distance - 0.001, rng);
+    if transmittance == 0.0 {
+        return black
+    }
+
+    let light_pdf = sample.pdf / count as f64;
+    let scatter_pdf = rec.material.pdf(ray, rec, direction);
+    spectrum(bsdf, ray) * spectrum(emitted, ray) * absorbed(&shadow, distance) * (transmittance * power_heuristic(light_pdf, scatter_pdf) / light_pdf)
+}
+
+// light left after going `distance` along a ray through the glass it's in
+fn absorbed(ray: &Ray, distance: f64) -> RGBColor {
+    match ray.absorption {
+        Some(absorption) => Material::transmittance(spectrum(absorption, ray), distance),
+        None => RGBColor::new(1.0, 1.0, 1.0),
+    }
+}
+
+// values at the wavelengths of spectral rays of what materials, lights and backgrounds give
+// as rgb colors
+fn spectrum(color: RGBColor, ray: &Ray) -> RGBColor {
+    match ray.wavelengths {
+        Some(wavelengths) => wavelengths.upsample(color),
+        None => color,
+    }
+}
+
 pub fn ray_color(ray: &Ray, world: &World, depth: i32, rng: &Rng) -> RGBColor {
+    trace(ray, world, depth, rng, None)
+}
+
+// `scatter_pdf` is the density with which the material at the previous bounce picked `ray`,
+// none for camera rays and specular bounces since light sampling can't find those paths
+fn trace(ray: &Ray, world: &World, depth: i32, rng: &Rng, scatter_pdf: Option<f64>) -> RGBColor {
     // If we've exceeded the ray bounce limit, no more light is gathered
     if depth <= 0 {
         return RGBColor::new(0.0, 0.0, 0.0)
     }
 
-    match world.hit(ray, 0.001, f64::INFINITY) {
-        Some(rec) => {
-            match rec.material.scatter(rng, ray, &rec) {
-                Some(scattered_ray) => {
-                    return rec.material.attenuation() * ray_color(&scattered_ray, world, depth - 1, rng)
-                },
-                None => return RGBColor::new(0.0,0.0,0.0),
-            }
-        },
-        None => {
-            let unit_direction = ray.direction.unit();
-            let t = 0.5*(unit_direction.y + 1.0);
-
-            let start_value = RGBColor::new(1.0, 1.0, 1.0); // white
-            let target_value = RGBColor::new(0.5, 0.7, 1.0); // blue
-
-            start_value*(1.0 - t) + target_value * t
-        },
+    let mut hit = world.hit(ray, 0.001, f64::INFINITY);
+    // the fog may scatter the ray before it gets to the surface or the background. Getting
+    // there has the probability of the fog's transmittance, which takes its place in the estimate
+    if let Some(fog) = &world.fog {
+        let t = fog.free_path(rng.f64()) / ray.direction.length();
+        let t_max = hit.as_ref().map_or(world.fog_distance / ray.direction.length(), |rec| rec.t);
+        if t < t_max {
+            hit = Some(fog.interaction(ray, t));
+        }
+    }
+    // and so may the volumes, which glow on the way
+    let t_max = hit.as_ref().map_or(f64::INFINITY, |rec| rec.t);
+    let volumes = delta_track(world.volumes(), ray, 0.001, t_max, rng);
+    if volumes.collision.is_some() {
+        hit = volumes.collision;
     }
+    let mut rec = match hit {
+        Some(rec) => rec,
+        None => return spectrum(volumes.emitted + world.background.color(ray.direction), ray),
+    };
 
-    
+    apply_bump(&mut rec);
+    // the glass the ray went through absorbed some of the light from the hit
+    let through = absorbed(ray, rec.t * ray.direction.length());
+    let mut emitted = spectrum(rec.material.emitted(&rec), ray);
+    if let Some(pdf) = scatter_pdf {
+        // the light may also have been reached by sampling it at the previous bounce
+        let light_pdf = world.light_pdf(ray.origin, ray.direction, rec.t * (1.0 + 1e-9));
+        if light_pdf > 0.0 {
+            emitted = emitted * power_heuristic(pdf, light_pdf);
+        }
+    }
+    let emitted = emitted + spectrum(volumes.emitted, ray);
+    let mut scattered = match rec.material.sample(rng, ray, &rec) {
+        Some(scattered) => scattered,
+        None => return emitted * through,
+    };
+    // specular bounces can't be found by light sampling
+    let direct = match (scattered.pdf, depth > 1) {
+        (Some(_), true) => sample_direct(world, ray, &rec, rng),
+        _ => RGBColor::new(0.0, 0.0, 0.0),
+    };
+    let mut attenuation = spectrum(scattered.attenuation, ray);
+    match (ray.wavelengths, scattered.ray.wavelengths) {
+        // the hero wavelength stands for the ones dispersed away from it from now on
+        (Some(before), Some(after)) if after.single && !before.single => attenuation = attenuation * RGBColor::new(3.0, 0.0, 0.0),
+        (wavelengths, None) => scattered.ray.wavelengths = wavelengths,
+        _ => (),
+    }
+    scattered.ray.absorption = rec.material.absorption_after(ray, &rec, scattered.ray.direction);
+    (emitted + direct + attenuation * trace(&scattered.ray, world, depth - 1, rng, scattered.pdf)) * through
 }
 
 pub fn random_scene(rng: &Rng) -> World {
     let mut world = World::new();
-    let ground_material = Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5));
+    let ground_material = Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into());
 
     world.add(Shape::Sphere { 
         center: Vector3::new(0.0, -1000.0, 0.0), 
         radius: 1000.0, 
-        material: ground_material.clone() 
+        material: ground_material
     });
 
     for a in -11..11 {
@@ -90,7 +209,7 @@ pub fn random_scene(rng: &Rng) -> World {
                 if choose_mat < 0.8 {
                     // diffuse
                     let albedo = RGBColor::random(rng) * RGBColor::random(rng);
-                    let sphere_material = Material::Lambertian(albedo);
+                    let sphere_material = Material::Lambertian(albedo.into());
                     world.add(Shape::Sphere { 
                         center,
                         radius: 0.2,
@@ -101,7 +220,7 @@ pub fn random_scene(rng: &Rng) -> World {
                     let albedo = RGBColor::random_interval(0.5, 1.0, rng);
                     let fuzz = rng.f64()*0.5;
 
-                    let sphere_material = Material::Metal(albedo, fuzz);
+                    let sphere_material = Material::Metal(albedo.into(), fuzz);
 
                     world.add(Shape::Sphere { 
                             center,
@@ -110,7 +229,7 @@ pub fn random_scene(rng: &Rng) -> World {
                     });
                 } else {
                     // glass
-                    let sphere_material = Material::Dielectric(RGBColor::new(1.0, 1.0, 1.0), 1.5);
+                    let sphere_material = Material::Dielectric(1.5.into(), RGBColor::new(0.0, 0.0, 0.0));
                     world.add(Shape::Sphere { 
                         center,
                         radius: 0.2,
@@ -121,28 +240,130 @@ pub fn random_scene(rng: &Rng) -> World {
         }
     }
 
-    let material1 = Material::Dielectric(RGBColor::new(1.0, 1.0, 1.0), 1.5);
+    let material1 = Material::Dielectric(1.5.into(), RGBColor::new(0.0, 0.0, 0.0));
     world.add(Shape::Sphere { 
         center: Vector3::new(0.0, 1.0, 0.0), 
         radius: 1.0, 
         material: material1 
     });
 
-    let material2 = Material::Lambertian(RGBColor::new(0.4, 0.2, 0.1));
+    let material2 = Material::Lambertian(RGBColor::new(0.4, 0.2, 0.1).into());
     world.add(Shape::Sphere { 
         center: Vector3::new(-4.0, 1.0, 0.0), 
         radius: 1.0, 
         material: material2 
     });
 
-    let material3 = Material::Metal(RGBColor::new(0.7, 0.6, 0.5), 0.0);
+    let material3 = Material::Metal(RGBColor::new(0.7, 0.6, 0.5).into(), 0.0);
     world.add(Shape::Sphere { 
         center: Vector3::new(4.0, 1.0, 0.0), 
         radius: 1.0, 
         material: material3
     });
 
-    return world;
+    world
 
     
-}
\ No newline at end of file
+}
+
+// the classic Cornell box, 555 units wide and lit only by the light in its ceiling.
+// Seen best from (278, 278, -800) looking at (278, 278, 0) with a 40 degrees field of view
+pub fn cornell_box() -> World {
+    let mut world = World::new();
+    world.background = Background::Solid(RGBColor::new(0.0, 0.0, 0.0));
+
+    let red = Material::Lambertian(RGBColor::new(0.65, 0.05, 0.05).into());
+    let white = Material::Lambertian(RGBColor::new(0.73, 0.73, 0.73).into());
+    let green = Material::Lambertian(RGBColor::new(0.12, 0.45, 0.15).into());
+    let light = Material::DiffuseLight(RGBColor::new(1.0, 1.0, 1.0), 15.0);
+
+    let quad = |corner: Vector3, edge_u: Vector3, edge_v: Vector3, material: Material| Shape::Quad {corner, edge_u, edge_v, material};
+    let (x, y, z) = (Vector3::new(555.0, 0.0, 0.0), Vector3::new(0.0, 555.0, 0.0), Vector3::new(0.0, 0.0, 555.0));
+
+    world.add(quad(x, y, z, green));
+    world.add(quad(Vector3::new(0.0, 0.0, 0.0), y, z, red));
+    // facing down into the box
+    world.add(quad(Vector3::new(213.0, 554.0, 227.0), Vector3::new(130.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 105.0), light));
+    world.add(quad(Vector3::new(0.0, 0.0, 0.0), x, z, white.clone()));
+    world.add(quad(y + x + z, -x, -z, white.clone()));
+    world.add(quad(z, x, y, white.clone()));
+
+    world.add(Shape::Sphere {center: Vector3::new(190.0, 90.0, 190.0), radius: 90.0, material: white});
+    world.add(Shape::Sphere {center: Vector3::new(370.0, 120.0, 350.0), radius: 120.0, material: Material::Metal(RGBColor::new(0.8, 0.85, 0.88).into(), 0.05)});
+
+    world
+}
+
+#[cfg(test)]
+mod test {
+    use super::ray_color;
+    use crate::{background::Background, color::RGBColor, hittable::{Shape, World}, material::Material, medium::{Medium, PhaseFunction},
+                ray::Ray, vector3::Vector3};
+
+    #[test]
+    fn emission_test() {
+        let mut world = World::new();
+        // light facing down, seen from below and from above
+        world.add(Shape::Quad {
+            corner: Vector3::new(-1.0, 1.0, -1.0),
+            edge_u: Vector3::new(2.0, 0.0, 0.0),
+            edge_v: Vector3::new(0.0, 0.0, 2.0),
+            material: Material::DiffuseLight(RGBColor::new(1.0, 0.5, 0.25), 4.0),
+        });
+        let rng = fastrand::Rng::with_seed(1);
+
+        let below = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        assert_eq!(RGBColor::new(4.0, 2.0, 1.0), ray_color(&below, &world, 10, &rng));
+        let above = Ray::new(Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(RGBColor::new(0.0, 0.0, 0.0), ray_color(&above, &world, 10, &rng));
+    }
+    #[test]
+    fn direct_lighting_test() {
+        // white floor under a spherical light, whose irradiance at the origin is
+        // pi * sin²(theta_max) * radiance
+        let mut world = World::new();
+        world.background = Background::Solid(RGBColor::new(0.0, 0.0, 0.0));
+        world.add(Shape::Plane {point: Vector3::new(0.0, 0.0, 0.0), normal: Vector3::new(0.0, 1.0, 0.0), material: Material::Lambertian(RGBColor::new(0.5, 0.5, 0.5).into())});
+        world.add(Shape::Sphere {center: Vector3::new(0.0, 2.0, 0.0), radius: 0.5, material: Material::DiffuseLight(RGBColor::new(1.0, 1.0, 1.0), 1.0)});
+        let rng = fastrand::Rng::with_seed(3);
+        let ray = Ray::new(Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+
+        let samples = 20000;
+        let mean = (0..samples).map(|_| ray_color(&ray, &world, 5, &rng).r()).sum::<f64>() / samples as f64;
+        let expected = 0.5 * 0.25 * 0.25;
+        assert!((mean - expected).abs() < 0.02 * expected, "{} != {}", mean, expected);
+    }
+    #[test]
+    fn fog_test() {
+        // black fog in front of a white background, only seen through it when at a distance
+        let mut world = World::new();
+        world.background = Background::Solid(RGBColor::new(1.0, 1.0, 1.0));
+        world.fog = Some(Medium::new(0.5, RGBColor::new(0.0, 0.0, 0.0).into(), PhaseFunction::Isotropic));
+        let rng = fastrand::Rng::with_seed(2);
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 2.0));
+
+        assert_eq!(RGBColor::new(0.0, 0.0, 0.0), ray_color(&ray, &world, 5, &rng));
+        world.fog_distance = 2.0;
+        let samples = 20000;
+        let mean = (0..samples).map(|_| ray_color(&ray, &world, 5, &rng).g()).sum::<f64>() / samples as f64;
+        assert!((mean - f64::exp(-1.0)).abs() < 0.01, "{}", mean);
+    }
+    #[test]
+    fn absorption_test() {
+        // a light inside a ball of glass that doesn't bend light, seen through the glass on
+        // the way in, and from inside the glass
+        let mut world = World::new();
+        let absorption = RGBColor::new(1.0, 0.5, 0.0);
+        world.add(Shape::Sphere {center: Vector3::new(0.0, 0.0, 0.0), radius: 2.0, material: Material::Dielectric(1.0.into(), absorption)});
+        world.add(Shape::Sphere {center: Vector3::new(0.0, 0.0, 0.0), radius: 0.5, material: Material::DiffuseLight(RGBColor::new(1.0, 1.0, 1.0), 1.0)});
+        let rng = fastrand::Rng::with_seed(4);
+
+        let outside = Ray::new(Vector3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0));
+        let color = ray_color(&outside, &world, 5, &rng);
+        assert!((color.r() - f64::exp(-1.5)).abs() < 1e-9 && (color.g() - f64::exp(-0.75)).abs() < 1e-9 && color.b() == 1.0, "{:?}", color);
+        let mut inside = Ray::new(Vector3::new(0.0, 0.0, -1.5), Vector3::new(0.0, 0.0, 2.0));
+        inside.absorption = Some(absorption);
+        let color = ray_color(&inside, &world, 5, &rng);
+        assert!((color.r() - f64::exp(-1.0)).abs() < 1e-9 && (color.g() - f64::exp(-0.5)).abs() < 1e-9, "{:?}", color);
+    }
+}
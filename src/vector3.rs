@@ -26,7 +26,7 @@ impl Vector3 {
         }
     }
     pub fn unit(&self) -> Vector3 {
-        self.clone()/self.length()
+        *self/self.length()
     }
     pub fn near_zero(&self) -> bool {
         let s = 1e-8;
@@ -96,6 +96,17 @@ impl std::ops::Mul<f64> for Vector3 {
         }
     }
 }
+impl std::ops::Index<usize> for Vector3 {
+    type Output = f64;
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector3 axis out of range: {}", axis),
+        }
+    }
+}
 impl std::ops::Mul<Vector3> for Vector3 {
     type Output = Vector3;
     fn mul(self, rhs: Vector3) -> Self::Output {
@@ -187,6 +198,12 @@ mod test {
         assert_eq!(res, vec1*vec2);
     }
     #[test]
+    fn index_test(){
+        let vec1 = Vector3::new(2.0, 4.0, 3.0);
+
+        assert_eq!((2.0, 4.0, 3.0), (vec1[0], vec1[1], vec1[2]));
+    }
+    #[test]
     fn neg_test(){
         let vec1 = Vector3::new(2.0, 4.0, 3.0);
         let res = Vector3::new(-2.0,-4.0,-3.0);